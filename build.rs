@@ -1,7 +1,12 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // SQL storage migrations are embedded at compile time
+    println!("cargo:rerun-if-changed=migrations");
+
     tonic_build::configure()
         .build_server(true)
         .build_client(true)
+        // Idempotency fingerprints need a stable encoding of the metadata map
+        .btree_map([".cert_agent.IssueCertificateRequest.metadata"])
         .compile_protos(&["proto/cert_agent.proto"], &["proto"])?;
     Ok(())
 }
@@ -0,0 +1,69 @@
+-- Certificate inventory for the SQLite and PostgreSQL storage backends.
+-- Only portable types are used so both databases share these migrations.
+
+CREATE TABLE certificates (
+    certificate_id TEXT PRIMARY KEY,
+    common_name TEXT NOT NULL,
+    -- Lowercased for case-insensitive lookups
+    common_name_lower TEXT NOT NULL,
+    status TEXT NOT NULL,
+    issued_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    serial_number TEXT NOT NULL,
+    fingerprint_sha256 TEXT NOT NULL,
+    signature_algorithm TEXT NOT NULL,
+    issuer_key_id TEXT NOT NULL,
+    profile TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    revoked_at BIGINT,
+    revocation_reason TEXT,
+    -- The full record as JSON; the columns above are copies for querying
+    record TEXT NOT NULL
+);
+
+CREATE INDEX certificates_by_issue ON certificates (issued_at, certificate_id);
+CREATE INDEX certificates_by_expiry ON certificates (expires_at, issued_at, certificate_id);
+CREATE INDEX certificates_by_status ON certificates (status, expires_at);
+CREATE INDEX certificates_by_serial ON certificates (serial_number);
+CREATE INDEX certificates_by_common_name ON certificates (common_name_lower);
+CREATE INDEX certificates_by_issuer ON certificates (issuer_key_id);
+CREATE INDEX certificates_by_profile ON certificates (profile);
+
+CREATE TABLE certificate_dns_names (
+    certificate_id TEXT NOT NULL REFERENCES certificates (certificate_id) ON DELETE CASCADE,
+    -- Lowercased
+    dns_name TEXT NOT NULL,
+    PRIMARY KEY (certificate_id, dns_name)
+);
+
+CREATE INDEX certificate_dns_names_by_name ON certificate_dns_names (dns_name);
+
+CREATE TABLE certificate_ip_addresses (
+    certificate_id TEXT NOT NULL REFERENCES certificates (certificate_id) ON DELETE CASCADE,
+    -- Canonical textual form
+    ip_address TEXT NOT NULL,
+    PRIMARY KEY (certificate_id, ip_address)
+);
+
+CREATE INDEX certificate_ip_addresses_by_address ON certificate_ip_addresses (ip_address);
+
+CREATE TABLE certificate_metadata (
+    certificate_id TEXT NOT NULL REFERENCES certificates (certificate_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (certificate_id, name)
+);
+
+CREATE INDEX certificate_metadata_by_value ON certificate_metadata (name, value);
+
+-- Monotonic counters such as the CRL number
+CREATE TABLE counters (
+    name TEXT PRIMARY KEY,
+    value BIGINT NOT NULL
+);
+
+-- Singleton agent state: the current CRL (base64 DER) and CA rotation (JSON)
+CREATE TABLE agent_state (
+    name TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
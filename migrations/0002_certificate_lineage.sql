@@ -0,0 +1,7 @@
+-- Renewal lineage: the ID of the first certificate in a renewal chain.
+-- Existing certificates are the start of their own lineage.
+
+ALTER TABLE certificates ADD COLUMN lineage_id TEXT NOT NULL DEFAULT '';
+UPDATE certificates SET lineage_id = certificate_id;
+
+CREATE INDEX certificates_by_lineage ON certificates (lineage_id, issued_at);
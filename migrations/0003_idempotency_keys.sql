@@ -0,0 +1,10 @@
+-- Responses to requests made under an idempotency key, replayed to retries
+-- until expires_at.
+
+CREATE TABLE idempotency_keys (
+    idempotency_key TEXT PRIMARY KEY,
+    record TEXT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+
+CREATE INDEX idempotency_keys_by_expiry ON idempotency_keys (expires_at);
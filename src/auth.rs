@@ -0,0 +1,710 @@
+use crate::certificate::certificate_fingerprint;
+use crate::config::AuthConfig;
+use crate::error::{CertAgentError, Result};
+use crate::store::CertificateStore;
+use futures::future::BoxFuture;
+use http::HeaderMap;
+use openssl::{memcmp, nid::Nid, sha::sha256, x509::X509};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tonic::{Request, Status};
+use tower::{Layer, Service};
+use tracing::warn;
+
+/// Permission to renew or revoke certificates issued to other callers.
+pub const ANY_OWNER: &str = "any-owner";
+
+/// Roles available without configuration. Entries in `AuthConfig::roles`
+/// with the same name replace them.
+pub fn builtin_roles() -> HashMap<String, Vec<String>> {
+    let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+    let reader = [
+        "GetCertificateStatus",
+        "GetCertificateBySerial",
+        "ListCertificates",
+        "GetCertificateLineage",
+        "WatchCertificates",
+        "GetCaRotationStatus",
+    ];
+
+    HashMap::from([
+        ("admin".to_string(), names(&["*"])),
+        (
+            "issuer".to_string(),
+            names(
+                &[
+                    &reader[..],
+                    &[
+                        "IssueCertificate",
+                        "SignCsr",
+                        "RenewCertificate",
+                        "RevokeCertificate",
+                    ],
+                ]
+                .concat(),
+            ),
+        ),
+        ("reader".to_string(), names(&reader)),
+    ])
+}
+
+/// Identity and permissions of a gRPC caller, attached to every request by
+/// `AuthLayer`.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    /// Token name or client certificate common name; empty when
+    /// authentication is disabled
+    pub name: String,
+    permissions: HashSet<String>,
+    /// Configured caller identities: token names and `client_roles` entries
+    identities: Arc<HashSet<String>>,
+}
+
+impl Caller {
+    fn unrestricted() -> Self {
+        Self {
+            name: String::new(),
+            permissions: HashSet::from(["*".to_string()]),
+            identities: Arc::default(),
+        }
+    }
+
+    /// The caller `AuthLayer` attached to `request`. A request that somehow
+    /// bypassed the layer gets a caller without permissions.
+    pub fn from_request<T>(request: &Request<T>) -> Self {
+        request
+            .extensions()
+            .get::<Caller>()
+            .cloned()
+            .unwrap_or_else(|| Self {
+                name: String::new(),
+                permissions: HashSet::new(),
+                identities: Arc::default(),
+            })
+    }
+
+    fn may_call(&self, rpc: &str) -> bool {
+        self.permissions.contains("*") || self.permissions.contains(rpc)
+    }
+
+    /// Whether the caller may renew or revoke a certificate issued to `owner`.
+    pub fn may_manage(&self, owner: &str) -> bool {
+        self.permissions.contains("*")
+            || self.permissions.contains(ANY_OWNER)
+            || (!self.name.is_empty() && self.name == owner)
+    }
+
+    /// Whether the caller may be issued a certificate for `common_name`. A
+    /// client certificate authenticates as its common name, so one naming a
+    /// configured identity is only issued to that identity, or by a caller
+    /// that may already call everything.
+    pub fn may_claim(&self, common_name: &str) -> bool {
+        self.permissions.contains("*")
+            || self.name == common_name
+            || !self.identities.contains(common_name)
+    }
+
+    /// Owner to record on certificates the caller issues.
+    pub fn owner(&self) -> Option<String> {
+        Some(self.name.clone()).filter(|name| !name.is_empty())
+    }
+}
+
+#[derive(Debug)]
+struct Authenticator {
+    enabled: bool,
+    /// SHA-256 of each token, so comparisons take the same time whatever
+    /// the token lengths
+    tokens: Vec<([u8; 32], String, Vec<String>)>,
+    client_roles: HashMap<String, Vec<String>>,
+    default_client_roles: Vec<String>,
+    roles: HashMap<String, Vec<String>>,
+    identities: Arc<HashSet<String>>,
+    /// Looked up for the status of client certificates
+    store: Arc<dyn CertificateStore>,
+    renewal_overlap_seconds: u64,
+}
+
+impl Authenticator {
+    fn new(
+        config: &AuthConfig,
+        store: Arc<dyn CertificateStore>,
+        renewal_overlap_seconds: u64,
+    ) -> Result<Self> {
+        let mut roles = builtin_roles();
+        roles.extend(config.roles.clone());
+
+        let referenced = config
+            .tokens
+            .iter()
+            .flat_map(|token| &token.roles)
+            .chain(config.client_roles.values().flatten())
+            .chain(&config.default_client_roles);
+        for role in referenced {
+            if !roles.contains_key(role) {
+                return Err(CertAgentError::Config(config::ConfigError::Message(
+                    format!("Unknown auth role: {}", role),
+                )));
+            }
+        }
+        if let Some(token) = config.tokens.iter().find(|token| token.token.is_empty()) {
+            return Err(CertAgentError::Config(config::ConfigError::Message(
+                format!("Auth token {} is empty", token.name),
+            )));
+        }
+
+        Ok(Self {
+            enabled: config.enabled,
+            tokens: config
+                .tokens
+                .iter()
+                .map(|token| {
+                    (
+                        sha256(token.token.as_bytes()),
+                        token.name.clone(),
+                        token.roles.clone(),
+                    )
+                })
+                .collect(),
+            client_roles: config.client_roles.clone(),
+            default_client_roles: config.default_client_roles.clone(),
+            roles,
+            identities: Arc::new(
+                config
+                    .tokens
+                    .iter()
+                    .map(|token| token.name.clone())
+                    .chain(config.client_roles.keys().cloned())
+                    .collect(),
+            ),
+            store,
+            renewal_overlap_seconds,
+        })
+    }
+
+    /// Authenticates the caller and checks it may call `rpc`. Every denial
+    /// is logged.
+    async fn authorize(
+        &self,
+        rpc: &str,
+        headers: &HeaderMap,
+        peer_certs: Option<&[X509]>,
+    ) -> Result<Caller> {
+        if !self.enabled {
+            return Ok(Caller::unrestricted());
+        }
+
+        let caller = self
+            .authenticate(headers, peer_certs)
+            .await
+            .inspect_err(|e| {
+                warn!("Rejected unauthenticated call to {}: {}", rpc, e);
+            })?;
+
+        if !caller.may_call(rpc) {
+            warn!("Denied {} to caller {}", rpc, caller.name);
+            return Err(CertAgentError::PermissionDenied(format!(
+                "Caller {} may not call {}",
+                caller.name, rpc
+            )));
+        }
+
+        Ok(caller)
+    }
+
+    async fn authenticate(
+        &self,
+        headers: &HeaderMap,
+        peer_certs: Option<&[X509]>,
+    ) -> Result<Caller> {
+        // A bearer token takes precedence over the client certificate
+        if let Some(value) = headers.get(http::header::AUTHORIZATION) {
+            let token = value
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .ok_or_else(|| {
+                    CertAgentError::Unauthenticated(
+                        "Authorization header must be a bearer token".to_string(),
+                    )
+                })?;
+            let digest = sha256(token.trim().as_bytes());
+
+            return self
+                .tokens
+                .iter()
+                .find(|(expected, _, _)| memcmp::eq(expected, &digest))
+                .map(|(_, name, roles)| self.caller(name, roles))
+                .ok_or_else(|| {
+                    CertAgentError::Unauthenticated("Invalid bearer token".to_string())
+                });
+        }
+
+        // The TLS handshake already verified the chain against grpc.tls.ca_file
+        if let Some(cert) = peer_certs.and_then(|certs| certs.first()) {
+            let common_name = cert
+                .subject_name()
+                .entries_by_nid(Nid::COMMONNAME)
+                .next()
+                .and_then(|entry| entry.data().as_utf8().ok())
+                .map(|name| name.to_string())
+                .ok_or_else(|| {
+                    CertAgentError::Unauthenticated(
+                        "Client certificate has no common name".to_string(),
+                    )
+                })?;
+            self.check_client_certificate(cert).await?;
+            let roles = self
+                .client_roles
+                .get(&common_name)
+                .unwrap_or(&self.default_client_roles);
+            return Ok(self.caller(&common_name, roles));
+        }
+
+        Err(CertAgentError::Unauthenticated(
+            "A bearer token or client certificate is required".to_string(),
+        ))
+    }
+
+    /// Accepts a client certificate only while the agent's record of it is
+    /// active, or superseded and within the renewal overlap, so that revoked
+    /// and retired certificates stop authenticating at once. Certificates
+    /// the agent did not issue have no record and are refused.
+    async fn check_client_certificate(&self, cert: &X509) -> Result<()> {
+        let serial_number = cert.serial_number().to_bn()?.to_hex_str()?.to_string();
+        let cert_record = self
+            .store
+            .get_certificate_by_serial(&serial_number)
+            .await?
+            .filter(|cert_record| {
+                certificate_fingerprint(cert)
+                    .is_ok_and(|fingerprint| fingerprint == cert_record.fingerprint_sha256)
+            })
+            .ok_or_else(|| {
+                CertAgentError::Unauthenticated(format!(
+                    "Client certificate {} was not issued by this agent",
+                    serial_number
+                ))
+            })?;
+
+        let now = chrono::Utc::now().timestamp();
+        let valid = match cert_record.status.as_str() {
+            "active" => true,
+            "superseded" => cert_record
+                .retired(self.renewal_overlap_seconds, now)
+                .is_none(),
+            _ => false,
+        };
+        if !valid {
+            return Err(CertAgentError::Unauthenticated(format!(
+                "Client certificate {} is no longer valid",
+                serial_number
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn caller(&self, name: &str, roles: &[String]) -> Caller {
+        Caller {
+            name: name.to_string(),
+            permissions: roles
+                .iter()
+                .filter_map(|role| self.roles.get(role))
+                .flatten()
+                .cloned()
+                .collect(),
+            identities: self.identities.clone(),
+        }
+    }
+}
+
+/// Tower layer in front of the gRPC service that authenticates each call
+/// and authorizes it by RPC name before it reaches a handler.
+#[derive(Debug, Clone)]
+pub struct AuthLayer {
+    authenticator: Arc<Authenticator>,
+}
+
+impl AuthLayer {
+    pub fn new(
+        config: &AuthConfig,
+        store: Arc<dyn CertificateStore>,
+        renewal_overlap_seconds: u64,
+    ) -> Result<Self> {
+        Ok(Self {
+            authenticator: Arc::new(Authenticator::new(config, store, renewal_overlap_seconds)?),
+        })
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            authenticator: self.authenticator.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    authenticator: Arc<Authenticator>,
+}
+
+impl<S, B> Service<http::Request<B>> for AuthService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        // Paths are /cert_agent.CertAgent/<Rpc>
+        let rpc = request
+            .uri()
+            .path()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let peer_certs = request
+            .extensions()
+            .get::<TlsConnectInfo<TcpConnectInfo>>()
+            .and_then(|info| info.peer_certs())
+            .map(|certs| {
+                certs
+                    .iter()
+                    .filter_map(|cert| X509::from_der(cert).ok())
+                    .collect::<Vec<_>>()
+            });
+
+        let authenticator = self.authenticator.clone();
+        // The clone is ready only if the original was; keep the ready one
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            match authenticator
+                .authorize(&rpc, request.headers(), peer_certs.as_deref())
+                .await
+            {
+                Ok(caller) => {
+                    request.extensions_mut().insert(caller);
+                    inner.call(request).await
+                }
+                Err(e) => Ok(auth_status(e).into_http()),
+            }
+        })
+    }
+}
+
+fn auth_status(e: CertAgentError) -> Status {
+    match e {
+        CertAgentError::Unauthenticated(reason) => Status::unauthenticated(reason),
+        CertAgentError::PermissionDenied(reason) => Status::permission_denied(reason),
+        e => Status::internal(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::tests::{request, test_manager};
+    use crate::certificate::CertificateManager;
+    use crate::config::TokenConfig;
+    use crate::store::MemoryStore;
+    use tempfile::TempDir;
+
+    const OVERLAP: u64 = 3600;
+
+    fn token(name: &str, token: &str, roles: &[&str]) -> TokenConfig {
+        TokenConfig {
+            name: name.to_string(),
+            token: token.to_string(),
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        }
+    }
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            enabled: true,
+            tokens: vec![
+                token("ci", "ci-token", &["issuer"]),
+                token("dashboard", "dashboard-token", &["reader"]),
+            ],
+            client_roles: HashMap::from([("deploy-bot".to_string(), vec!["admin".to_string()])]),
+            default_client_roles: vec!["reader".to_string()],
+            roles: HashMap::new(),
+        }
+    }
+
+    async fn authenticator(
+        config: AuthConfig,
+    ) -> (Authenticator, CertificateManager, Arc<MemoryStore>, TempDir) {
+        let (cert_manager, store, dir) = test_manager().await;
+        let authenticator = Authenticator::new(&config, store.clone(), OVERLAP).unwrap();
+        (authenticator, cert_manager, store, dir)
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::AUTHORIZATION,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        headers
+    }
+
+    /// Issues a client certificate for `common_name`, returning its id and
+    /// the certificate as the TLS handshake would present it.
+    async fn client_cert(cert_manager: &CertificateManager, common_name: &str) -> (String, X509) {
+        let issued = cert_manager
+            .issue_certificate(request(common_name))
+            .await
+            .unwrap();
+        let cert = X509::from_pem(issued.certificate_pem.as_bytes()).unwrap();
+        (issued.certificate_id, cert)
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_and_invalid_credentials() {
+        let (authenticator, _cert_manager, _store, _dir) = authenticator(config()).await;
+        let mut basic = HeaderMap::new();
+        basic.insert(
+            http::header::AUTHORIZATION,
+            "Basic Y2k6Y2ktdG9rZW4=".parse().unwrap(),
+        );
+
+        for headers in [HeaderMap::new(), bearer("wrong-token"), basic] {
+            let error = authenticator
+                .authorize("ListCertificates", &headers, None)
+                .await
+                .unwrap_err();
+            assert!(matches!(error, CertAgentError::Unauthenticated(_)));
+        }
+    }
+
+    #[tokio::test]
+    async fn denies_rpcs_outside_the_callers_roles() {
+        let (authenticator, _cert_manager, _store, _dir) = authenticator(config()).await;
+
+        let caller = authenticator
+            .authorize("ListCertificates", &bearer("dashboard-token"), None)
+            .await
+            .unwrap();
+        assert_eq!(caller.name, "dashboard");
+
+        let error = authenticator
+            .authorize("IssueCertificate", &bearer("dashboard-token"), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, CertAgentError::PermissionDenied(_)));
+    }
+
+    #[tokio::test]
+    async fn configured_roles_replace_the_builtin_ones() {
+        let mut config = config();
+        config.roles = HashMap::from([
+            (
+                "reader".to_string(),
+                vec!["GetCertificateStatus".to_string()],
+            ),
+            (
+                "auditor".to_string(),
+                vec!["ListCertificates".to_string(), ANY_OWNER.to_string()],
+            ),
+        ]);
+        config
+            .tokens
+            .push(token("audit", "audit-token", &["auditor"]));
+        let (authenticator, _cert_manager, _store, _dir) = authenticator(config).await;
+
+        let reader = bearer("dashboard-token");
+        assert!(authenticator
+            .authorize("GetCertificateStatus", &reader, None)
+            .await
+            .is_ok());
+        assert!(matches!(
+            authenticator
+                .authorize("ListCertificates", &reader, None)
+                .await,
+            Err(CertAgentError::PermissionDenied(_))
+        ));
+
+        let auditor = authenticator
+            .authorize("ListCertificates", &bearer("audit-token"), None)
+            .await
+            .unwrap();
+        assert!(auditor.may_manage("ci"));
+    }
+
+    #[tokio::test]
+    async fn refuses_unknown_roles_in_the_configuration() {
+        let (_cert_manager, store, _dir) = test_manager().await;
+        let unknown = vec!["auditor".to_string()];
+
+        let mut tokens = config();
+        tokens
+            .tokens
+            .push(token("audit", "audit-token", &["auditor"]));
+        let mut client_roles = config();
+        client_roles
+            .client_roles
+            .insert("audit-bot".to_string(), unknown.clone());
+        let mut default_client_roles = config();
+        default_client_roles.default_client_roles = unknown;
+
+        for config in [tokens, client_roles, default_client_roles] {
+            let error = Authenticator::new(&config, store.clone(), OVERLAP).unwrap_err();
+            assert!(matches!(error, CertAgentError::Config(_)));
+        }
+    }
+
+    #[tokio::test]
+    async fn lets_callers_manage_only_their_own_certificates() {
+        let (authenticator, cert_manager, _store, _dir) = authenticator(config()).await;
+
+        let ci = authenticator
+            .authorize("RevokeCertificate", &bearer("ci-token"), None)
+            .await
+            .unwrap();
+        assert!(ci.may_manage("ci"));
+        assert!(!ci.may_manage("dashboard"));
+        // Certificates issued while authentication was disabled have no owner
+        assert!(!ci.may_manage(""));
+
+        let (_, cert) = client_cert(&cert_manager, "deploy-bot").await;
+        let admin = authenticator
+            .authorize("RevokeCertificate", &HeaderMap::new(), Some(&[cert]))
+            .await
+            .unwrap();
+        assert!(admin.may_manage("ci"));
+        assert!(admin.may_manage(""));
+
+        // A request that bypassed the layer
+        let anonymous = Caller::from_request(&Request::new(()));
+        assert!(!anonymous.may_manage(""));
+    }
+
+    #[tokio::test]
+    async fn maps_client_certificates_to_roles_by_common_name() {
+        let (authenticator, cert_manager, _store, _dir) = authenticator(config()).await;
+        let (_, deploy_bot) = client_cert(&cert_manager, "deploy-bot").await;
+        let deploy_bot = [deploy_bot];
+        let (_, worker) = client_cert(&cert_manager, "worker.example.com").await;
+
+        let caller = authenticator
+            .authorize("RevokeCertificate", &HeaderMap::new(), Some(&deploy_bot))
+            .await
+            .unwrap();
+        assert_eq!(caller.name, "deploy-bot");
+
+        // Unlisted common names get the default client roles
+        let worker = [worker];
+        let caller = authenticator
+            .authorize("ListCertificates", &HeaderMap::new(), Some(&worker))
+            .await
+            .unwrap();
+        assert_eq!(caller.name, "worker.example.com");
+        assert!(matches!(
+            authenticator
+                .authorize("IssueCertificate", &HeaderMap::new(), Some(&worker))
+                .await,
+            Err(CertAgentError::PermissionDenied(_))
+        ));
+
+        // A bearer token takes precedence over the client certificate
+        let caller = authenticator
+            .authorize(
+                "ListCertificates",
+                &bearer("dashboard-token"),
+                Some(&deploy_bot),
+            )
+            .await
+            .unwrap();
+        assert_eq!(caller.name, "dashboard");
+    }
+
+    #[tokio::test]
+    async fn refuses_revoked_retired_and_foreign_client_certificates() {
+        let (authenticator, cert_manager, store, _dir) = authenticator(config()).await;
+        let authorize = |cert: X509| {
+            let authenticator = &authenticator;
+            async move {
+                authenticator
+                    .authorize("ListCertificates", &HeaderMap::new(), Some(&[cert]))
+                    .await
+            }
+        };
+
+        let (revoked_id, revoked) = client_cert(&cert_manager, "worker.example.com").await;
+        assert!(authorize(revoked.clone()).await.is_ok());
+        cert_manager
+            .revoke_certificate(&revoked_id, None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            authorize(revoked).await,
+            Err(CertAgentError::Unauthenticated(_))
+        ));
+
+        // Superseded certificates keep working for the renewal overlap only
+        let (renewed_id, renewed) = client_cert(&cert_manager, "worker.example.com").await;
+        cert_manager
+            .renew_certificate(&renewed_id, None, Default::default())
+            .await
+            .unwrap();
+        assert!(authorize(renewed.clone()).await.is_ok());
+        let mut cert_record = store.get_certificate(&renewed_id).await.unwrap().unwrap();
+        cert_record.superseded_at = Some(chrono::Utc::now().timestamp() - OVERLAP as i64 - 1);
+        store.put_certificate(&cert_record);
+        assert!(matches!(
+            authorize(renewed).await,
+            Err(CertAgentError::Unauthenticated(_))
+        ));
+
+        let (other_manager, _other_store, _other_dir) = test_manager().await;
+        let (_, foreign) = client_cert(&other_manager, "deploy-bot").await;
+        assert!(matches!(
+            authorize(foreign).await,
+            Err(CertAgentError::Unauthenticated(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reserves_configured_identities_for_their_callers() {
+        let (authenticator, cert_manager, _store, _dir) = authenticator(config()).await;
+
+        let ci = authenticator
+            .authorize("IssueCertificate", &bearer("ci-token"), None)
+            .await
+            .unwrap();
+        assert!(ci.may_claim("ci"));
+        assert!(ci.may_claim("api.example.com"));
+        assert!(!ci.may_claim("deploy-bot"));
+        assert!(!ci.may_claim("dashboard"));
+
+        let (_, cert) = client_cert(&cert_manager, "deploy-bot").await;
+        let admin = authenticator
+            .authorize("IssueCertificate", &HeaderMap::new(), Some(&[cert]))
+            .await
+            .unwrap();
+        assert!(admin.may_claim("dashboard"));
+
+        assert!(Caller::unrestricted().may_claim("deploy-bot"));
+    }
+}
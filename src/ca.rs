@@ -0,0 +1,475 @@
+use crate::certificate::{
+    generate_private_key, parse_csr, random_serial, signing_digest, SignatureAlgorithm,
+};
+use crate::config::CertificateConfig;
+use crate::error::{CertAgentError, Result};
+use crate::keys::KeyStorage;
+use clap::Subcommand;
+use openssl::{
+    asn1::Asn1Time,
+    pkey::{PKeyRef, Private},
+    x509::{
+        extension::{AuthorityKeyIdentifier, BasicConstraints, KeyUsage, SubjectKeyIdentifier},
+        X509Builder, X509Name, X509NameRef, X509Ref, X509Req, X509,
+    },
+};
+use std::path::Path;
+use tokio::fs;
+use tracing::{info, warn};
+
+/// Offline CA maintenance, run instead of the service. The root key is only
+/// ever touched by these commands; the service itself issues from an
+/// intermediate configured via `ca_cert_path`, `ca_key_path` and `ca_chain_path`.
+#[derive(Subcommand, Debug)]
+pub enum CaCommand {
+    /// Generate a self-signed root CA certificate and key
+    GenerateRoot {
+        #[arg(long)]
+        cert_out: String,
+        #[arg(long)]
+        key_out: String,
+        #[arg(long, default_value = "Cert Agent Root CA")]
+        common_name: String,
+        #[arg(long, default_value = "Cert Agent")]
+        organization: String,
+        #[arg(long, default_value_t = 7300)]
+        validity_days: u32,
+        /// Maximum number of intermediate CAs below the root
+        #[arg(long, default_value_t = 1)]
+        path_len: u32,
+    },
+    /// Sign an intermediate CA CSR with the root key
+    SignIntermediate {
+        #[arg(long)]
+        root_cert: String,
+        #[arg(long)]
+        root_key: String,
+        /// PKCS#10 CSR in PEM or DER, e.g. from `openssl req -new`
+        #[arg(long)]
+        csr: String,
+        #[arg(long)]
+        cert_out: String,
+        #[arg(long, default_value_t = 1825)]
+        validity_days: u32,
+        /// Maximum number of further CAs below the intermediate
+        #[arg(long, default_value_t = 0)]
+        path_len: u32,
+    },
+}
+
+pub async fn run(command: CaCommand, config: &CertificateConfig) -> Result<()> {
+    let signature_algorithm = config.signature_algorithm.parse()?;
+    let keys = KeyStorage::new(&config.key_storage)?;
+
+    match command {
+        CaCommand::GenerateRoot {
+            cert_out,
+            key_out,
+            common_name,
+            organization,
+            validity_days,
+            path_len,
+        } => {
+            refuse_overwrite(&cert_out)?;
+            refuse_overwrite(&key_out)?;
+
+            let key = generate_private_key(config.key_algorithm, config.key_size)?;
+
+            let mut name = X509Name::builder()?;
+            name.append_entry_by_text("CN", &common_name)?;
+            name.append_entry_by_text("O", &organization)?;
+            let name = name.build();
+
+            let mut builder = ca_builder(&name, &name, validity_days, path_len)?;
+            builder.set_pubkey(&key)?;
+            append_key_identifiers(&mut builder, None)?;
+            builder.sign(&key, signing_digest(&key, signature_algorithm))?;
+            let cert = builder.build();
+
+            write_output(&cert_out, &cert.to_pem()?).await?;
+            keys.write(&key_out, &key).await?;
+
+            info!(
+                "Generated root CA {} valid for {} days: {} / {}",
+                common_name, validity_days, cert_out, key_out
+            );
+            warn!("Move {} offline once intermediates are signed", key_out);
+        }
+        CaCommand::SignIntermediate {
+            root_cert,
+            root_key,
+            csr,
+            cert_out,
+            validity_days,
+            path_len,
+        } => {
+            refuse_overwrite(&cert_out)?;
+
+            let root_cert = X509::from_pem(&fs::read(&root_cert).await?)?;
+            let root_key = keys.read(&root_key).await?;
+            let csr = parse_csr(&fs::read(&csr).await?)?;
+
+            let cert = sign_intermediate(
+                &root_cert,
+                &root_key,
+                &csr,
+                validity_days,
+                path_len,
+                signature_algorithm,
+            )?;
+            write_output(&cert_out, &cert.to_pem()?).await?;
+
+            info!(
+                "Signed intermediate CA {:?} valid for {} days: {}",
+                cert.subject_name(),
+                validity_days,
+                cert_out
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Issues an intermediate CA certificate for `csr`, signed by the root.
+pub fn sign_intermediate(
+    root_cert: &X509Ref,
+    root_key: &PKeyRef<Private>,
+    csr: &X509Req,
+    validity_days: u32,
+    path_len: u32,
+    signature_algorithm: SignatureAlgorithm,
+) -> Result<X509> {
+    if !root_cert.public_key()?.public_eq(root_key) {
+        return Err(CertAgentError::Certificate(
+            "Root key does not match root certificate".to_string(),
+        ));
+    }
+
+    // An intermediate must not outlive the root that vouches for it
+    if root_cert.not_after() < Asn1Time::days_from_now(validity_days)? {
+        return Err(CertAgentError::InvalidRequest(format!(
+            "Intermediate validity of {} days exceeds the root's expiry {}",
+            validity_days,
+            root_cert.not_after()
+        )));
+    }
+
+    let mut builder = ca_builder(
+        csr.subject_name(),
+        root_cert.subject_name(),
+        validity_days,
+        path_len,
+    )?;
+    let public_key = csr.public_key()?;
+    builder.set_pubkey(&public_key)?;
+    append_key_identifiers(&mut builder, Some(root_cert))?;
+    builder.sign(root_key, signing_digest(root_key, signature_algorithm))?;
+
+    Ok(builder.build())
+}
+
+/// Checks that `issuing` chains through `chain` (issuer after subject) and
+/// that each link is signed by the next certificate.
+pub fn verify_chain(issuing: &X509Ref, chain: &[X509]) -> Result<()> {
+    if chain.is_empty() {
+        return Err(CertAgentError::Certificate(
+            "CA chain file contains no certificates".to_string(),
+        ));
+    }
+
+    let mut subject = issuing;
+    for issuer in chain {
+        let issuer_key = issuer.public_key()?;
+        if !subject.verify(&issuer_key)? {
+            return Err(CertAgentError::Certificate(format!(
+                "{:?} is not signed by {:?}",
+                subject.subject_name(),
+                issuer.subject_name()
+            )));
+        }
+        subject = issuer;
+    }
+
+    let root_key = subject.public_key()?;
+    if !subject.verify(&root_key)? {
+        warn!(
+            "CA chain ends with {:?}, which is not a self-signed root",
+            subject.subject_name()
+        );
+    }
+
+    Ok(())
+}
+
+fn ca_builder(
+    subject: &X509NameRef,
+    issuer: &X509NameRef,
+    validity_days: u32,
+    path_len: u32,
+) -> Result<X509Builder> {
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_subject_name(subject)?;
+    builder.set_issuer_name(issuer)?;
+
+    let serial_int = random_serial()?.to_asn1_integer()?;
+    builder.set_serial_number(&serial_int)?;
+
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(validity_days)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+
+    builder.append_extension(
+        BasicConstraints::new()
+            .critical()
+            .ca()
+            .pathlen(path_len)
+            .build()?,
+    )?;
+    builder.append_extension(
+        KeyUsage::new()
+            .critical()
+            .key_cert_sign()
+            .crl_sign()
+            .build()?,
+    )?;
+
+    Ok(builder)
+}
+
+/// Adds SubjectKeyIdentifier, plus AuthorityKeyIdentifier when an issuer
+/// other than the certificate itself is given. The public key must be set.
+fn append_key_identifiers(builder: &mut X509Builder, issuer: Option<&X509Ref>) -> Result<()> {
+    let ski = SubjectKeyIdentifier::new().build(&builder.x509v3_context(issuer, None))?;
+    builder.append_extension(ski)?;
+
+    if issuer.is_some() {
+        let aki = AuthorityKeyIdentifier::new()
+            .keyid(true)
+            .build(&builder.x509v3_context(issuer, None))?;
+        builder.append_extension(aki)?;
+    }
+
+    Ok(())
+}
+
+fn refuse_overwrite(path: &str) -> Result<()> {
+    if Path::new(path).exists() {
+        return Err(CertAgentError::InvalidRequest(format!(
+            "Refusing to overwrite existing file {}",
+            path
+        )));
+    }
+    Ok(())
+}
+
+async fn write_output(path: &str, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::write(path, contents).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::tests::{request, test_manager_with};
+    use crate::config::{Config, KeyAlgorithm};
+    use openssl::{
+        hash::MessageDigest,
+        pkey::PKey,
+        stack::Stack,
+        x509::{store::X509StoreBuilder, X509StoreContext},
+    };
+    use tempfile::TempDir;
+
+    fn key() -> PKey<Private> {
+        generate_private_key(KeyAlgorithm::EcdsaP256, 2048).unwrap()
+    }
+
+    /// A self-signed root over `key`, built as `generate-root` builds it.
+    fn root(key: &PKeyRef<Private>, validity_days: u32) -> X509 {
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_text("CN", "Test Root CA").unwrap();
+        let name = name.build();
+
+        let mut builder = ca_builder(&name, &name, validity_days, 1).unwrap();
+        builder.set_pubkey(key).unwrap();
+        append_key_identifiers(&mut builder, None).unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn csr(key: &PKeyRef<Private>, common_name: &str) -> X509Req {
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let mut builder = X509Req::builder().unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn intermediate(
+        issuer: &X509Ref,
+        issuer_key: &PKeyRef<Private>,
+        key: &PKeyRef<Private>,
+        path_len: u32,
+    ) -> X509 {
+        sign_intermediate(
+            issuer,
+            issuer_key,
+            &csr(key, "Test Intermediate CA"),
+            365,
+            path_len,
+            SignatureAlgorithm::Sha256,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn refuses_a_root_key_that_does_not_match_the_root() {
+        let root_key = key();
+        let root = root(&root_key, 3650);
+
+        let error = sign_intermediate(
+            &root,
+            &key(),
+            &csr(&key(), "Test Intermediate CA"),
+            365,
+            0,
+            SignatureAlgorithm::Sha256,
+        )
+        .unwrap_err();
+        assert!(matches!(error, CertAgentError::Certificate(_)));
+    }
+
+    #[test]
+    fn refuses_an_intermediate_outliving_the_root() {
+        let root_key = key();
+        let root = root(&root_key, 30);
+
+        let error = sign_intermediate(
+            &root,
+            &root_key,
+            &csr(&key(), "Test Intermediate CA"),
+            365,
+            0,
+            SignatureAlgorithm::Sha256,
+        )
+        .unwrap_err();
+        assert!(matches!(error, CertAgentError::InvalidRequest(_)));
+    }
+
+    #[test]
+    fn verifies_chains_in_issuer_order() {
+        let root_key = key();
+        let root = root(&root_key, 3650);
+        let upper_key = key();
+        let upper = intermediate(&root, &root_key, &upper_key, 1);
+        let issuing = intermediate(&upper, &upper_key, &key(), 0);
+
+        assert!(verify_chain(&issuing, &[upper.clone(), root.clone()]).is_ok());
+        assert!(verify_chain(&issuing, &[root.clone(), upper.clone()]).is_err());
+        assert!(verify_chain(&issuing, &[root]).is_err());
+        assert!(verify_chain(&issuing, &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn issues_from_an_intermediate_up_to_the_root() {
+        let ca_dir = TempDir::new().unwrap();
+        let path = |name: &str| ca_dir.path().join(name).to_string_lossy().into_owned();
+        let mut config = Config::default().certificate;
+        config.key_algorithm = KeyAlgorithm::EcdsaP256;
+
+        run(
+            CaCommand::GenerateRoot {
+                cert_out: path("root.crt"),
+                key_out: path("root.key"),
+                common_name: "Test Root CA".to_string(),
+                organization: "Example".to_string(),
+                validity_days: 3650,
+                path_len: 1,
+            },
+            &config,
+        )
+        .await
+        .unwrap();
+        let intermediate_key = key();
+        std::fs::write(
+            path("intermediate.csr"),
+            csr(&intermediate_key, "Test Intermediate CA")
+                .to_pem()
+                .unwrap(),
+        )
+        .unwrap();
+        run(
+            CaCommand::SignIntermediate {
+                root_cert: path("root.crt"),
+                root_key: path("root.key"),
+                csr: path("intermediate.csr"),
+                cert_out: path("intermediate.crt"),
+                validity_days: 365,
+                path_len: 0,
+            },
+            &config,
+        )
+        .await
+        .unwrap();
+        std::fs::write(
+            path("intermediate.key"),
+            intermediate_key.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap();
+
+        // A chain file that does not lead to the intermediate's issuer is refused
+        std::fs::write(path("other.crt"), root(&key(), 3650).to_pem().unwrap()).unwrap();
+        let keys = KeyStorage::new(&config.key_storage).unwrap();
+        assert!(crate::certificate::CaCredentials::load(
+            &path("intermediate.crt"),
+            &path("intermediate.key"),
+            Some(&path("other.crt")),
+            &keys,
+        )
+        .await
+        .is_err());
+
+        let (cert_manager, _store, _dir) = test_manager_with(|config| {
+            config.certificate.ca_cert_path = path("intermediate.crt");
+            config.certificate.ca_key_path = path("intermediate.key");
+            config.certificate.ca_chain_path = Some(path("root.crt"));
+        })
+        .await;
+        let issued = cert_manager
+            .issue_certificate(request("api.example.com"))
+            .await
+            .unwrap();
+
+        let root = X509::from_pem(&std::fs::read(path("root.crt")).unwrap()).unwrap();
+        let intermediate =
+            X509::from_pem(&std::fs::read(path("intermediate.crt")).unwrap()).unwrap();
+        assert_eq!(
+            issued.ca_chain_pem,
+            [
+                String::from_utf8(intermediate.to_pem().unwrap()).unwrap(),
+                String::from_utf8(root.to_pem().unwrap()).unwrap(),
+            ]
+        );
+        assert_eq!(issued.ca_certificate_pem, issued.ca_chain_pem[0]);
+
+        // The leaf verifies against the root alone, given the intermediate
+        let mut trusted = X509StoreBuilder::new().unwrap();
+        trusted.add_cert(root).unwrap();
+        let trusted = trusted.build();
+        let mut untrusted = Stack::new().unwrap();
+        untrusted.push(intermediate).unwrap();
+        let leaf = X509::from_pem(issued.certificate_pem.as_bytes()).unwrap();
+        let mut context = X509StoreContext::new().unwrap();
+        assert!(context
+            .init(&trusted, &leaf, &untrusted, |context| context.verify_cert())
+            .unwrap());
+    }
+}
@@ -1,13 +1,29 @@
 use anyhow::Result;
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 use std::path::Path;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub grpc: GrpcConfig,
     pub redis: RedisConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
     pub certificate: CertificateConfig,
     pub watcher: WatcherConfig,
+    #[serde(default)]
+    pub revocation: RevocationConfig,
+    #[serde(default)]
+    pub rotation: RotationConfig,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub idempotency: IdempotencyConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,9 +35,22 @@ pub struct GrpcConfig {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TlsConfig {
+    /// Serving certificate, followed by any intermediates
     pub cert_file: String,
     pub key_file: String,
+    /// CA bundle client certificates are verified against; enables mutual TLS
     pub ca_file: Option<String>,
+    /// Let clients without a certificate connect even when `ca_file` is set
+    #[serde(default)]
+    pub client_auth_optional: bool,
+    /// Issue `cert_file`/`key_file` from the agent's own CA, at startup and
+    /// on every watcher pass, when they are missing, close to expiry or
+    /// signed by another CA
+    #[serde(default)]
+    pub bootstrap: bool,
+    /// DNS names and IP addresses for the bootstrapped certificate
+    #[serde(default)]
+    pub server_names: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,22 +61,318 @@ pub struct RedisConfig {
     pub command_timeout_secs: u64,
 }
 
+/// Where the certificate inventory, CRL and CA rotation state are kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Database URL for the SQL backends, e.g.
+    /// `sqlite:///var/lib/cert-agent/cert-agent.db?mode=rwc` or
+    /// `postgres://cert_agent@db/cert_agent`; Redis uses `redis.url`
+    pub url: Option<String>,
+    pub max_connections: u32,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Redis,
+            url: None,
+            max_connections: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageBackend {
+    #[default]
+    Redis,
+    Sqlite,
+    Postgres,
+    /// Kept in process memory and lost on restart; for tests and single-node
+    /// development
+    Memory,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CertificateConfig {
+    /// Issuing CA certificate: the intermediate when `ca_chain_path` is set
     pub ca_cert_path: String,
     pub ca_key_path: String,
+    /// PEM bundle of the certificates above the issuing CA, ending with the
+    /// root. The root key stays offline; see `cert-agent generate-root`.
+    #[serde(default)]
+    pub ca_chain_path: Option<String>,
     pub storage_path: String,
     pub default_validity_days: u32,
     pub renewal_threshold_days: u32,
     pub key_size: u32,
+    #[serde(default)]
+    pub key_algorithm: KeyAlgorithm,
     pub signature_algorithm: String,
+    /// Named certificate profiles, merged over the built-in `default`,
+    /// `server`, `client`, `code-signing` and `workload` profiles
+    #[serde(default)]
+    pub profiles: HashMap<String, CertificateProfile>,
+    #[serde(default)]
+    pub key_storage: KeyStorageConfig,
+}
+
+/// How private keys are kept on disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyStorageConfig {
+    /// File holding the passphrase CA and leaf keys are encrypted with
+    /// (PKCS#8, AES-256-CBC); keys are stored unencrypted without one
+    pub passphrase_file: Option<String>,
+    /// Environment variable holding the passphrase, instead of a file
+    pub passphrase_env: Option<String>,
+    /// Keep a copy of each issued certificate's key under `storage_path`;
+    /// when disabled the key is only returned to the caller
+    pub persist_leaf_keys: bool,
+}
+
+impl Default for KeyStorageConfig {
+    fn default() -> Self {
+        Self {
+            passphrase_file: None,
+            passphrase_env: None,
+            persist_leaf_keys: true,
+        }
+    }
+}
+
+/// Certificate template selected per request by name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CertificateProfile {
+    /// KeyUsage bits such as `digital-signature` or `key-encipherment`;
+    /// derived from the key type when empty
+    pub key_usages: Vec<String>,
+    /// ExtendedKeyUsage names (`server-auth`, `client-auth`, `code-signing`,
+    /// `email-protection`, `time-stamping`, `ocsp-signing`) or dotted OIDs
+    pub extended_key_usages: Vec<String>,
+    pub max_validity_days: Option<u32>,
+    /// Key types accepted for this profile; any when empty
+    pub allowed_key_algorithms: Vec<KeyAlgorithm>,
+    /// Subject fields the request must supply: `organization`,
+    /// `organizational_unit`, `country`, `state`, `locality`
+    pub required_subject_fields: Vec<String>,
+    pub extensions: Vec<ProfileExtension>,
 }
 
+/// Additional extension copied verbatim into certificates of a profile.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileExtension {
+    pub oid: String,
+    #[serde(default)]
+    pub critical: bool,
+    /// Base64 of the DER-encoded extension value
+    pub value: String,
+}
+
+/// Key type used for generated CA and leaf private keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyAlgorithm {
+    /// RSA with `CertificateConfig::key_size` bits
+    #[default]
+    Rsa,
+    /// ECDSA on NIST P-256 (prime256v1)
+    EcdsaP256,
+    /// ECDSA on NIST P-384 (secp384r1)
+    EcdsaP384,
+    Ed25519,
+}
+
+impl KeyAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyAlgorithm::Rsa => "rsa",
+            KeyAlgorithm::EcdsaP256 => "ecdsa-p256",
+            KeyAlgorithm::EcdsaP384 => "ecdsa-p384",
+            KeyAlgorithm::Ed25519 => "ed25519",
+        }
+    }
+}
+
+impl fmt::Display for KeyAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for KeyAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rsa" => Ok(KeyAlgorithm::Rsa),
+            "ecdsa-p256" | "p256" | "p-256" | "prime256v1" => Ok(KeyAlgorithm::EcdsaP256),
+            "ecdsa-p384" | "p384" | "p-384" | "secp384r1" => Ok(KeyAlgorithm::EcdsaP384),
+            "ed25519" => Ok(KeyAlgorithm::Ed25519),
+            other => Err(format!("Unsupported key algorithm: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct WatcherConfig {
     pub check_interval_seconds: u64,
     pub renewal_threshold_days: u32,
     pub max_concurrent_renewals: usize,
+    /// How long certificate records are kept after the certificate
+    /// expires, whatever their status, before they are deleted; 0 keeps
+    /// them forever
+    pub expired_retention_days: u32,
+    /// How often index entries left by deleted records are cleaned up;
+    /// 0 disables the repair
+    pub index_repair_interval_seconds: u64,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        Self {
+            check_interval_seconds: 3600, // 1 hour
+            renewal_threshold_days: 30,
+            max_concurrent_renewals: 10,
+            expired_retention_days: 90,
+            index_repair_interval_seconds: 86400, // 1 day
+        }
+    }
+}
+
+/// CRL publishing, the OCSP responder and the HTTP endpoint that serves both.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RevocationConfig {
+    /// Address for the HTTP revocation endpoint; disabled when unset
+    pub http_bind_address: Option<String>,
+    /// Externally reachable URL of the HTTP endpoint, embedded in issued
+    /// certificates as the CRL distribution point and OCSP responder location
+    pub base_url: Option<String>,
+    pub crl_refresh_interval_seconds: u64,
+    /// How far ahead the CRL nextUpdate field is set
+    pub crl_validity_hours: u32,
+    /// Delegated OCSP signing certificate; responses are signed by the CA when unset
+    pub ocsp_signer_cert_path: Option<String>,
+    pub ocsp_signer_key_path: Option<String>,
+    /// How far ahead the OCSP nextUpdate field is set
+    pub ocsp_validity_seconds: u64,
+    /// How long a certificate replaced by a renewal stays valid alongside
+    /// its successor before the CRL and OCSP report it revoked as superseded
+    pub renewal_overlap_seconds: u64,
+}
+
+impl Default for RevocationConfig {
+    fn default() -> Self {
+        Self {
+            http_bind_address: None,
+            base_url: None,
+            crl_refresh_interval_seconds: 3600, // 1 hour
+            crl_validity_hours: 24,
+            ocsp_signer_cert_path: None,
+            ocsp_signer_key_path: None,
+            ocsp_validity_seconds: 3600,    // 1 hour
+            renewal_overlap_seconds: 86400, // 1 day
+        }
+    }
+}
+
+/// gRPC caller authentication and role-based authorization. Token names and
+/// client certificate common names share one namespace of caller identities.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Every caller is unrestricted when disabled
+    pub enabled: bool,
+    /// Static bearer tokens, sent as `authorization: Bearer <token>`
+    pub tokens: Vec<TokenConfig>,
+    /// Roles of mTLS clients by client certificate common name. Certificates
+    /// for these names are only issued to the client itself or to callers
+    /// allowed every RPC
+    pub client_roles: HashMap<String, Vec<String>>,
+    /// Roles of verified mTLS clients not listed in `client_roles`
+    pub default_client_roles: Vec<String>,
+    /// Additional roles, or replacements for the built-in admin, issuer and
+    /// reader roles: RPC names, "*" for every RPC, "any-owner" to renew or
+    /// revoke certificates issued to other callers
+    pub roles: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenConfig {
+    /// Caller identity, recorded as the owner of certificates it issues
+    pub name: String,
+    pub token: String,
+    pub roles: Vec<String>,
+}
+
+/// CA rotation: how quickly certificates are moved to a newly activated CA.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RotationConfig {
+    /// Time the watcher has to re-issue every active certificate under the
+    /// new CA; re-issuance is spread evenly over this window
+    pub reissue_window_hours: u64,
+}
+
+/// Idempotency keys on IssueCertificate, RenewCertificate and RevokeCertificate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    /// How long the response to a request is replayed for retries carrying
+    /// the same key
+    pub ttl_seconds: u64,
+}
+
+/// Issuance policy: which names, addresses and lifetimes callers may request.
+/// Empty lists impose no restriction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyConfig {
+    /// DNS names (and common names that are host names) must equal or lie below one of these
+    pub allowed_domains: Vec<String>,
+    /// Names equal to or below these are refused, even under an allowed domain
+    pub denied_domains: Vec<String>,
+    pub allow_wildcards: bool,
+    /// CIDR ranges IP SANs must fall into, e.g. "10.0.0.0/8"
+    pub allowed_ip_ranges: Vec<IpNet>,
+    /// Upper bound on validity across all profiles
+    pub max_validity_days: Option<u32>,
+    /// Metadata keys every request must set to a non-empty value
+    pub required_metadata: Vec<String>,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        Self {
+            allowed_domains: Vec::new(),
+            denied_domains: Vec::new(),
+            allow_wildcards: true,
+            allowed_ip_ranges: Vec::new(),
+            max_validity_days: None,
+            required_metadata: Vec::new(),
+        }
+    }
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        Self {
+            reissue_window_hours: 168, // 1 week
+        }
+    }
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            ttl_seconds: 86400, // 1 day
+        }
+    }
 }
 
 impl Config {
@@ -82,20 +407,26 @@ impl Default for Config {
                 connection_timeout_secs: 5,
                 command_timeout_secs: 3,
             },
+            storage: StorageConfig::default(),
             certificate: CertificateConfig {
                 ca_cert_path: "./certs/ca.crt".to_string(),
                 ca_key_path: "./certs/ca.key".to_string(),
+                ca_chain_path: None,
                 storage_path: "./certs/storage".to_string(),
                 default_validity_days: 365,
                 renewal_threshold_days: 30,
                 key_size: 2048,
+                key_algorithm: KeyAlgorithm::Rsa,
                 signature_algorithm: "sha256".to_string(),
+                profiles: HashMap::new(),
+                key_storage: KeyStorageConfig::default(),
             },
-            watcher: WatcherConfig {
-                check_interval_seconds: 3600, // 1 hour
-                renewal_threshold_days: 30,
-                max_concurrent_renewals: 10,
-            },
+            watcher: WatcherConfig::default(),
+            revocation: RevocationConfig::default(),
+            rotation: RotationConfig::default(),
+            policy: PolicyConfig::default(),
+            auth: AuthConfig::default(),
+            idempotency: IdempotencyConfig::default(),
         }
     }
 }
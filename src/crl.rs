@@ -0,0 +1,191 @@
+use crate::certificate::{
+    sign_tbs, signature_algorithm_identifier, CertificateManager, SignatureAlgorithm,
+};
+use crate::config::RevocationConfig;
+use crate::error::{CertAgentError, Result};
+use crate::store::CertificateRecord;
+use openssl::{
+    bn::BigNum,
+    hash::{hash, MessageDigest},
+    pkey::{PKeyRef, Private},
+    x509::X509Ref,
+};
+use std::time::Duration;
+use tracing::{error, info, warn};
+use x509_cert::{
+    crl::{CertificateList, RevokedCert, TbsCertList},
+    der::{
+        asn1::{BitString, GeneralizedTime, OctetString, Uint, UtcTime},
+        Decode, Encode,
+    },
+    ext::{
+        pkix::{AuthorityKeyIdentifier, CrlNumber, CrlReason},
+        AsExtension, Extension,
+    },
+    name::Name,
+    serial_number::SerialNumber,
+    spki::SubjectPublicKeyInfoOwned,
+    time::Time,
+    Version,
+};
+
+/// Maps a free-form revocation reason (as accepted by `RevokeCertificate`) to
+/// an RFC 5280 reason code. Unknown reasons fall back to `unspecified`.
+pub fn parse_reason(reason: &str) -> CrlReason {
+    let normalized: String = reason
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    match normalized.as_str() {
+        "keycompromise" => CrlReason::KeyCompromise,
+        "cacompromise" => CrlReason::CaCompromise,
+        "affiliationchanged" => CrlReason::AffiliationChanged,
+        "superseded" => CrlReason::Superseded,
+        "cessationofoperation" => CrlReason::CessationOfOperation,
+        "certificatehold" => CrlReason::CertificateHold,
+        "privilegewithdrawn" => CrlReason::PrivilegeWithdrawn,
+        "aacompromise" => CrlReason::AaCompromise,
+        _ => CrlReason::Unspecified,
+    }
+}
+
+/// Encodes a Unix timestamp as UTCTime through 2049 and GeneralizedTime
+/// afterwards, as required by RFC 5280.
+pub fn asn1_time(timestamp: i64) -> Result<Time> {
+    let duration = Duration::from_secs(timestamp.max(0) as u64);
+    match UtcTime::from_unix_duration(duration) {
+        Ok(time) => Ok(Time::UtcTime(time)),
+        Err(_) => Ok(Time::GeneralTime(GeneralizedTime::from_unix_duration(
+            duration,
+        )?)),
+    }
+}
+
+/// Key identifier of `ca_cert`: its SubjectKeyIdentifier when present,
+/// otherwise the SHA-1 of the subject public key (RFC 5280 method 1).
+pub fn ca_key_identifier(ca_cert: &X509Ref) -> Result<Vec<u8>> {
+    if let Some(ski) = ca_cert.subject_key_id() {
+        return Ok(ski.as_slice().to_vec());
+    }
+
+    let spki = SubjectPublicKeyInfoOwned::from_der(&ca_cert.public_key()?.public_key_to_der()?)?;
+    Ok(hash(MessageDigest::sha1(), spki.subject_public_key.raw_bytes())?.to_vec())
+}
+
+/// Builds a DER-encoded X.509 v2 CRL listing every revoked record that carries
+/// a serial number, signed by the CA.
+pub fn build_crl(
+    ca_cert: &X509Ref,
+    ca_key: &PKeyRef<Private>,
+    signature_algorithm: SignatureAlgorithm,
+    revoked: &[CertificateRecord],
+    crl_number: u64,
+    this_update: i64,
+    next_update: i64,
+) -> Result<Vec<u8>> {
+    let issuer = Name::from_der(&ca_cert.subject_name().to_der()?)?;
+    let algorithm = signature_algorithm_identifier(ca_key, signature_algorithm)?;
+
+    let mut revoked_certs = Vec::with_capacity(revoked.len());
+    for record in revoked {
+        if record.serial_number.is_empty() {
+            warn!(
+                "Revoked certificate {} has no recorded serial number, omitting from CRL",
+                record.certificate_id
+            );
+            continue;
+        }
+
+        let serial = BigNum::from_hex_str(&record.serial_number)?;
+        let reason = record
+            .revocation_reason
+            .as_deref()
+            .map(parse_reason)
+            .unwrap_or(CrlReason::Unspecified);
+
+        // RFC 5280 5.3.1: the reason code should be absent rather than unspecified
+        let crl_entry_extensions = if reason == CrlReason::Unspecified {
+            None
+        } else {
+            Some(vec![reason.to_extension(&issuer, &[])?])
+        };
+
+        revoked_certs.push(RevokedCert {
+            serial_number: SerialNumber::new(&serial.to_vec())?,
+            revocation_date: asn1_time(record.revoked_at.unwrap_or(record.issued_at))?,
+            crl_entry_extensions,
+        });
+    }
+
+    let authority_key_id = AuthorityKeyIdentifier {
+        key_identifier: Some(OctetString::new(ca_key_identifier(ca_cert)?)?),
+        authority_cert_issuer: None,
+        authority_cert_serial_number: None,
+    };
+    let crl_number = CrlNumber(Uint::new(&crl_number.to_be_bytes())?);
+    let crl_extensions: Vec<Extension> = vec![
+        authority_key_id.to_extension(&issuer, &[])?,
+        crl_number.to_extension(&issuer, &[])?,
+    ];
+
+    let tbs_cert_list = TbsCertList {
+        version: Version::V2,
+        signature: algorithm.clone(),
+        issuer,
+        this_update: asn1_time(this_update)?,
+        next_update: Some(asn1_time(next_update)?),
+        revoked_certificates: if revoked_certs.is_empty() {
+            None
+        } else {
+            Some(revoked_certs)
+        },
+        crl_extensions: Some(crl_extensions),
+    };
+
+    let signature = sign_tbs(ca_key, signature_algorithm, &tbs_cert_list.to_der()?)?;
+    let crl = CertificateList {
+        tbs_cert_list,
+        signature_algorithm: algorithm,
+        signature: BitString::from_bytes(&signature)?,
+    };
+
+    crl.to_der().map_err(CertAgentError::from)
+}
+
+/// Periodically regenerates the CRL so `nextUpdate` never lapses, independent
+/// of revocation activity.
+#[derive(Debug, Clone)]
+pub struct CrlPublisher {
+    cert_manager: CertificateManager,
+    config: RevocationConfig,
+}
+
+impl CrlPublisher {
+    pub fn new(cert_manager: CertificateManager, config: RevocationConfig) -> Self {
+        Self {
+            cert_manager,
+            config,
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        info!(
+            "Starting CRL publisher with {} second intervals",
+            self.config.crl_refresh_interval_seconds
+        );
+
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(
+            self.config.crl_refresh_interval_seconds,
+        ));
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.cert_manager.publish_crl().await {
+                error!("Error publishing CRL: {}", e);
+            }
+        }
+    }
+}
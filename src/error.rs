@@ -8,8 +8,11 @@ pub enum CertAgentError {
     #[error("Redis error: {0}")]
     Redis(#[from] redis::RedisError),
 
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
     #[error("gRPC error: {0}")]
-    Grpc(#[from] tonic::Status),
+    Grpc(#[from] Box<tonic::Status>),
 
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
@@ -23,6 +26,9 @@ pub enum CertAgentError {
     #[error("OpenSSL error: {0}")]
     OpenSsl(#[from] openssl::error::ErrorStack),
 
+    #[error("ASN.1 error: {0}")]
+    Asn1(#[from] x509_cert::der::Error),
+
     #[error("UTF-8 error: {0}")]
     Utf8(#[from] std::string::FromUtf8Error),
 
@@ -44,6 +50,21 @@ pub enum CertAgentError {
     #[error("Invalid request: {0}")]
     InvalidRequest(String),
 
+    #[error("Unauthenticated: {0}")]
+    Unauthenticated(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Denied by issuance policy: {0}")]
+    PolicyViolation(String),
+
+    #[error("Invalid state: {0}")]
+    InvalidState(String),
+
+    #[error("Request in progress: {0}")]
+    InProgress(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
@@ -1,5 +1,19 @@
-use crate::certificate::{CertificateManager, CertificateRequest};
-use crate::redis_client::RedisClient;
+use crate::auth::{AuthLayer, Caller};
+use crate::certificate::{
+    csr_common_name, CertificateManager, CertificateRequest, RenewalOverrides,
+};
+use crate::config::KeyAlgorithm;
+use crate::error::CertAgentError;
+use crate::idempotency::{Claim, Idempotency, IDEMPOTENCY_KEY_HEADER};
+use crate::rotation::{CaRotation, RotationStatus};
+use crate::store::{
+    current_certificate, CertificateQuery, CertificateRecord, CertificateStore, ListSort,
+};
+use crate::tls::ServingCertificate;
+use futures::StreamExt;
+use prost::Message;
+use std::future::Future;
+use std::sync::Arc;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 use tracing::{error, info, warn};
@@ -13,21 +27,39 @@ use cert_agent::{
     *,
 };
 
+/// ListCertificates page size when the request leaves it unset.
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
 #[derive(Debug)]
 pub struct CertAgentService {
     cert_manager: CertificateManager,
-    redis: RedisClient,
+    store: Arc<dyn CertificateStore>,
+    rotation: CaRotation,
+    idempotency: Idempotency,
 }
 
 impl CertAgentService {
-    pub fn new(cert_manager: CertificateManager, redis: RedisClient) -> Self {
+    pub fn new(
+        cert_manager: CertificateManager,
+        store: Arc<dyn CertificateStore>,
+        rotation: CaRotation,
+        idempotency: Idempotency,
+    ) -> Self {
         Self {
             cert_manager,
-            redis,
+            store,
+            rotation,
+            idempotency,
         }
     }
 
-    pub async fn start(&self, bind_address: String) -> crate::error::Result<()> {
+    pub async fn start(
+        &self,
+        bind_address: String,
+        tls: Option<ServingCertificate>,
+        auth: AuthLayer,
+    ) -> crate::error::Result<()> {
         let addr = bind_address.parse().map_err(|e| {
             crate::error::CertAgentError::InvalidRequest(format!("Invalid bind address: {}", e))
         })?;
@@ -36,16 +68,164 @@ impl CertAgentService {
 
         info!("Starting gRPC server on {}", addr);
 
-        tonic::transport::Server::builder()
-            .add_service(service)
-            .serve(addr)
+        let router = tonic::transport::Server::builder()
+            .layer(auth)
+            .add_service(service);
+        let served = match tls {
+            Some(tls) => {
+                let listener = tokio::net::TcpListener::bind(addr).await?;
+                router.serve_with_incoming(tls.incoming(listener)).await
+            }
+            None => router.serve(addr).await,
+        };
+
+        served.map_err(|e| {
+            crate::error::CertAgentError::Internal(format!("gRPC server error: {}", e))
+        })?;
+
+        Ok(())
+    }
+
+    /// Refuses callers that neither own the certificate nor may manage
+    /// certificates issued to others.
+    async fn check_owner(&self, caller: &Caller, certificate_id: &str) -> crate::error::Result<()> {
+        let cert_record = self
+            .cert_manager
+            .get_certificate_status(certificate_id)
+            .await?
+            .ok_or_else(|| CertAgentError::CertificateNotFound(certificate_id.to_string()))?;
+
+        if !caller.may_manage(&cert_record.owner) {
+            warn!(
+                "Denied caller {} access to certificate {} owned by {}",
+                caller.name, certificate_id, cert_record.owner
+            );
+            return Err(CertAgentError::PermissionDenied(format!(
+                "Certificate {} was not issued to {}",
+                certificate_id, caller.name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Refuses to issue a certificate that would authenticate as a configured
+    /// caller identity to anyone but that identity.
+    fn check_claim(caller: &Caller, common_name: &str) -> crate::error::Result<()> {
+        if !caller.may_claim(common_name) {
+            warn!(
+                "Denied caller {} a certificate for identity {}",
+                caller.name, common_name
+            );
+            return Err(CertAgentError::PermissionDenied(format!(
+                "Common name {} is the identity of another caller",
+                common_name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Carries out `run` once per idempotency key. A retry carrying the key
+    /// of a completed request gets that response back, flagged as replayed,
+    /// instead. `stored` picks what of a response is kept for retries;
+    /// `None` keeps nothing, so a retry carries the request out again.
+    async fn idempotent<R, F, Fut>(
+        &self,
+        caller: &Caller,
+        method: &str,
+        key: Option<String>,
+        request: &[u8],
+        stored: impl FnOnce(&R) -> Option<R>,
+        run: F,
+    ) -> std::result::Result<(R, bool), Status>
+    where
+        R: Message + Default,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = std::result::Result<R, Status>>,
+    {
+        let Some(key) = key else {
+            return run().await.map(|response| (response, false));
+        };
+
+        let pending = match self
+            .idempotency
+            .claim(&caller.name, method, &key, request)
             .await
-            .map_err(|e| {
-                crate::error::CertAgentError::Internal(format!("gRPC server error: {}", e))
-            })?;
+            .map_err(idempotency_error)?
+        {
+            Claim::New(pending) => pending,
+            Claim::Replay(response) => {
+                info!("Replaying {} response for idempotency key {}", method, key);
+                let response = R::decode(response.as_slice()).map_err(|e| {
+                    Status::internal(format!("Stored idempotent response is corrupt: {}", e))
+                })?;
+                return Ok((response, true));
+            }
+        };
+
+        let result = run().await;
+        let saved = match result.as_ref().ok().and_then(stored) {
+            Some(response) => {
+                self.idempotency
+                    .complete(pending, &response.encode_to_vec())
+                    .await
+            }
+            None => self.idempotency.release(pending).await,
+        };
+        if let Err(e) = saved {
+            warn!(
+                "Failed to record the outcome of {} request with idempotency key {}: {}",
+                method, key, e
+            );
+        }
 
+        result.map(|response| (response, false))
+    }
+
+    /// Refuses an idempotency key for a request whose response holds a new
+    /// private key when keys are not kept: a retry could not return the key
+    /// of the certificate the first attempt issued.
+    fn check_replayable(&self, method: &str, key: &Option<String>) -> crate::error::Result<()> {
+        if key.is_some() && !self.cert_manager.keeps_leaf_keys() {
+            return Err(CertAgentError::InvalidState(format!(
+                "Idempotency keys are not accepted for {} while \
+                 key_storage.persist_leaf_keys is off",
+                method
+            )));
+        }
         Ok(())
     }
+
+    /// The private key of a certificate whose issuance is replayed; keys
+    /// are not kept with the stored response. Empty for a certificate
+    /// signed from a CSR, whose key the agent never had.
+    async fn replayed_private_key(
+        &self,
+        certificate_id: &str,
+    ) -> std::result::Result<String, Status> {
+        let client_key = self
+            .cert_manager
+            .get_certificate_status(certificate_id)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read certificate: {}", e)))?
+            .is_some_and(|cert_record| cert_record.client_key);
+        if client_key {
+            return Ok(String::new());
+        }
+
+        self.cert_manager
+            .stored_private_key_pem(certificate_id)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read private key: {}", e)))?
+            .ok_or_else(|| {
+                Status::failed_precondition(format!(
+                    "The private key of certificate {} is not kept, so the original response \
+                     cannot be returned; retry with a new idempotency key",
+                    certificate_id
+                ))
+            })
+    }
 }
 
 #[tonic::async_trait]
@@ -54,46 +234,154 @@ impl CertAgent for CertAgentService {
         &self,
         request: Request<IssueCertificateRequest>,
     ) -> std::result::Result<Response<IssueCertificateResponse>, Status> {
-        let req = request.into_inner();
+        let caller = Caller::from_request(&request);
+        let key = idempotency_key(&request, &request.get_ref().idempotency_key)
+            .map_err(idempotency_error)?;
+        let mut req = request.into_inner();
+        req.idempotency_key.clear();
+        let fingerprint = req.encode_to_vec();
+        self.check_replayable("IssueCertificate", &key)
+            .map_err(idempotency_error)?;
 
         info!("Issuing certificate for CN: {}", req.common_name);
 
+        let key_algorithm = if req.key_algorithm.is_empty() {
+            None
+        } else {
+            Some(
+                req.key_algorithm
+                    .parse::<KeyAlgorithm>()
+                    .map_err(Status::invalid_argument)?,
+            )
+        };
+
         let cert_request = CertificateRequest {
             common_name: req.common_name,
             dns_names: req.dns_names,
             ip_addresses: req.ip_addresses,
             validity_days: req.validity_days as u32,
-            organization: Some(req.organization),
-            organizational_unit: Some(req.organizational_unit),
-            country: Some(req.country),
-            state: Some(req.state),
-            locality: Some(req.locality),
+            organization: non_empty(req.organization),
+            organizational_unit: non_empty(req.organizational_unit),
+            country: non_empty(req.country),
+            state: non_empty(req.state),
+            locality: non_empty(req.locality),
+            metadata: req.metadata.into_iter().collect(),
+            key_algorithm,
+            profile: non_empty(req.profile),
+            owner: caller.owner(),
+        };
+        Self::check_claim(&caller, &cert_request.common_name).map_err(owner_error)?;
+
+        let issue = || async move {
+            match self.cert_manager.issue_certificate(cert_request).await {
+                Ok(cert) => Ok(IssueCertificateResponse {
+                    certificate_id: cert.certificate_id,
+                    certificate_pem: cert.certificate_pem,
+                    private_key_pem: cert.private_key_pem.unwrap_or_default(),
+                    ca_certificate_pem: cert.ca_certificate_pem,
+                    expires_at: cert.expires_at.timestamp(),
+                    status: cert_status_to_proto(&cert.status),
+                    serial_number: cert.serial_number,
+                    ca_chain_pem: cert.ca_chain_pem,
+                }),
+                Err(CertAgentError::InvalidRequest(reason)) => {
+                    warn!("Rejected certificate request: {}", reason);
+                    Err(Status::invalid_argument(reason))
+                }
+                Err(CertAgentError::PolicyViolation(reason)) => {
+                    Err(Status::permission_denied(reason))
+                }
+                Err(e) => {
+                    error!("Failed to issue certificate: {}", e);
+                    Err(Status::internal(format!(
+                        "Failed to issue certificate: {}",
+                        e
+                    )))
+                }
+            }
+        };
+
+        let (mut response, replayed) = self
+            .idempotent(
+                &caller,
+                "IssueCertificate",
+                key,
+                &fingerprint,
+                |response: &IssueCertificateResponse| {
+                    Some(IssueCertificateResponse {
+                        private_key_pem: String::new(),
+                        ..response.clone()
+                    })
+                },
+                issue,
+            )
+            .await?;
+        if replayed {
+            response.private_key_pem = self.replayed_private_key(&response.certificate_id).await?;
+        } else {
+            info!(
+                "Successfully issued certificate: {}",
+                response.certificate_id
+            );
+        }
+        Ok(Response::new(response))
+    }
+
+    async fn sign_csr(
+        &self,
+        request: Request<SignCsrRequest>,
+    ) -> std::result::Result<Response<SignCsrResponse>, Status> {
+        let caller = Caller::from_request(&request);
+        let req = request.into_inner();
+
+        info!("Signing CSR for CN: {}", req.common_name);
+
+        let cert_request = CertificateRequest {
+            common_name: req.common_name,
+            dns_names: req.dns_names,
+            ip_addresses: req.ip_addresses,
+            // Zero lets the profile pick the default validity
+            validity_days: req.validity_days as u32,
+            organization: non_empty(req.organization),
+            organizational_unit: non_empty(req.organizational_unit),
+            country: non_empty(req.country),
+            state: non_empty(req.state),
+            locality: non_empty(req.locality),
             metadata: req.metadata,
+            key_algorithm: None,
+            profile: non_empty(req.profile),
+            owner: caller.owner(),
+        };
+        // An empty common name is taken from the CSR
+        let common_name = match cert_request.common_name.as_str() {
+            "" => csr_common_name(&req.csr).unwrap_or_default(),
+            common_name => common_name.to_string(),
         };
+        Self::check_claim(&caller, &common_name).map_err(owner_error)?;
 
-        match self.cert_manager.issue_certificate(cert_request).await {
+        match self.cert_manager.sign_csr(&req.csr, cert_request).await {
             Ok(cert) => {
-                let response = IssueCertificateResponse {
+                let response = SignCsrResponse {
                     certificate_id: cert.certificate_id,
                     certificate_pem: cert.certificate_pem,
-                    private_key_pem: cert.private_key_pem,
                     ca_certificate_pem: cert.ca_certificate_pem,
                     expires_at: cert.expires_at.timestamp(),
                     status: cert_status_to_proto(&cert.status),
+                    serial_number: cert.serial_number,
+                    ca_chain_pem: cert.ca_chain_pem,
                 };
 
-                info!(
-                    "Successfully issued certificate: {}",
-                    response.certificate_id
-                );
+                info!("Successfully signed CSR: {}", response.certificate_id);
                 Ok(Response::new(response))
             }
+            Err(CertAgentError::InvalidRequest(reason)) => {
+                warn!("Rejected CSR: {}", reason);
+                Err(Status::invalid_argument(reason))
+            }
+            Err(CertAgentError::PolicyViolation(reason)) => Err(Status::permission_denied(reason)),
             Err(e) => {
-                error!("Failed to issue certificate: {}", e);
-                Err(Status::internal(format!(
-                    "Failed to issue certificate: {}",
-                    e
-                )))
+                error!("Failed to sign CSR: {}", e);
+                Err(Status::internal(format!("Failed to sign CSR: {}", e)))
             }
         }
     }
@@ -102,82 +390,165 @@ impl CertAgent for CertAgentService {
         &self,
         request: Request<RenewCertificateRequest>,
     ) -> std::result::Result<Response<RenewCertificateResponse>, Status> {
-        let req = request.into_inner();
+        let caller = Caller::from_request(&request);
+        let key = idempotency_key(&request, &request.get_ref().idempotency_key)
+            .map_err(idempotency_error)?;
+        let mut req = request.into_inner();
+        req.idempotency_key.clear();
+        let fingerprint = req.encode_to_vec();
+        // A renewal from a CSR returns no key
+        if req.csr.is_empty() {
+            self.check_replayable("RenewCertificate", &key)
+                .map_err(idempotency_error)?;
+        }
 
         info!("Renewing certificate: {}", req.certificate_id);
 
+        self.check_owner(&caller, &req.certificate_id)
+            .await
+            .map_err(owner_error)?;
+
         let validity_days = if req.validity_days > 0 {
             Some(req.validity_days as u32)
         } else {
             None
         };
+        let key_algorithm = if req.key_algorithm.is_empty() {
+            None
+        } else {
+            Some(
+                req.key_algorithm
+                    .parse::<KeyAlgorithm>()
+                    .map_err(Status::invalid_argument)?,
+            )
+        };
+        let overrides = RenewalOverrides {
+            organization: non_empty(req.organization),
+            organizational_unit: non_empty(req.organizational_unit),
+            country: non_empty(req.country),
+            state: non_empty(req.state),
+            locality: non_empty(req.locality),
+            key_algorithm,
+            profile: non_empty(req.profile),
+            csr: Some(req.csr).filter(|csr| !csr.is_empty()),
+        };
 
-        match self
-            .cert_manager
-            .renew_certificate(&req.certificate_id, validity_days)
-            .await
-        {
-            Ok(cert) => {
-                let response = RenewCertificateResponse {
+        let certificate_id = req.certificate_id.as_str();
+        let renew = || async move {
+            match self
+                .cert_manager
+                .renew_certificate(certificate_id, validity_days, overrides)
+                .await
+            {
+                Ok(cert) => Ok(RenewCertificateResponse {
                     certificate_id: cert.certificate_id,
                     certificate_pem: cert.certificate_pem,
-                    private_key_pem: cert.private_key_pem,
+                    private_key_pem: cert.private_key_pem.unwrap_or_default(),
                     expires_at: cert.expires_at.timestamp(),
                     status: cert_status_to_proto(&cert.status),
-                };
-
-                info!(
-                    "Successfully renewed certificate: {}",
-                    response.certificate_id
-                );
-                Ok(Response::new(response))
-            }
-            Err(e) => {
-                error!("Failed to renew certificate {}: {}", req.certificate_id, e);
-                Err(Status::internal(format!(
-                    "Failed to renew certificate: {}",
-                    e
-                )))
+                    serial_number: cert.serial_number,
+                }),
+                Err(CertAgentError::InvalidRequest(reason)) => {
+                    warn!("Rejected renewal of {}: {}", certificate_id, reason);
+                    Err(Status::invalid_argument(reason))
+                }
+                Err(CertAgentError::PolicyViolation(reason)) => {
+                    Err(Status::permission_denied(reason))
+                }
+                Err(e) => {
+                    error!("Failed to renew certificate {}: {}", certificate_id, e);
+                    Err(Status::internal(format!(
+                        "Failed to renew certificate: {}",
+                        e
+                    )))
+                }
             }
+        };
+
+        let (mut response, replayed) = self
+            .idempotent(
+                &caller,
+                "RenewCertificate",
+                key,
+                &fingerprint,
+                |response: &RenewCertificateResponse| {
+                    Some(RenewCertificateResponse {
+                        private_key_pem: String::new(),
+                        ..response.clone()
+                    })
+                },
+                renew,
+            )
+            .await?;
+        if replayed {
+            response.private_key_pem = self.replayed_private_key(&response.certificate_id).await?;
+        } else {
+            info!(
+                "Successfully renewed certificate: {}",
+                response.certificate_id
+            );
         }
+        Ok(Response::new(response))
     }
 
     async fn revoke_certificate(
         &self,
         request: Request<RevokeCertificateRequest>,
     ) -> std::result::Result<Response<RevokeCertificateResponse>, Status> {
-        let req = request.into_inner();
+        let caller = Caller::from_request(&request);
+        let key = idempotency_key(&request, &request.get_ref().idempotency_key)
+            .map_err(idempotency_error)?;
+        let mut req = request.into_inner();
+        req.idempotency_key.clear();
+        let fingerprint = req.encode_to_vec();
 
         info!("Revoking certificate: {}", req.certificate_id);
 
-        match self
-            .cert_manager
-            .revoke_certificate(&req.certificate_id, Some(&req.reason))
+        self.check_owner(&caller, &req.certificate_id)
             .await
-        {
-            Ok(()) => {
-                let response = RevokeCertificateResponse {
-                    certificate_id: req.certificate_id,
-                    success: true,
-                    message: "Certificate revoked successfully".to_string(),
-                };
+            .map_err(owner_error)?;
 
-                info!(
-                    "Successfully revoked certificate: {}",
-                    response.certificate_id
-                );
-                Ok(Response::new(response))
-            }
-            Err(e) => {
-                error!("Failed to revoke certificate {}: {}", req.certificate_id, e);
-                let response = RevokeCertificateResponse {
-                    certificate_id: req.certificate_id,
-                    success: false,
-                    message: format!("Failed to revoke certificate: {}", e),
-                };
-                Ok(Response::new(response))
+        let revoke = || async move {
+            match self
+                .cert_manager
+                .revoke_certificate(&req.certificate_id, Some(&req.reason))
+                .await
+            {
+                Ok(()) => {
+                    info!("Successfully revoked certificate: {}", req.certificate_id);
+                    Ok(RevokeCertificateResponse {
+                        certificate_id: req.certificate_id,
+                        success: true,
+                        message: "Certificate revoked successfully".to_string(),
+                    })
+                }
+                Err(CertAgentError::InvalidState(reason)) => {
+                    warn!("Refused to revoke {}: {}", req.certificate_id, reason);
+                    Err(Status::failed_precondition(reason))
+                }
+                Err(e) => {
+                    error!("Failed to revoke certificate {}: {}", req.certificate_id, e);
+                    Ok(RevokeCertificateResponse {
+                        certificate_id: req.certificate_id,
+                        success: false,
+                        message: format!("Failed to revoke certificate: {}", e),
+                    })
+                }
             }
-        }
+        };
+
+        // Failures are not kept, so a retry attempts the revocation again
+        let (response, _) = self
+            .idempotent(
+                &caller,
+                "RevokeCertificate",
+                key,
+                &fingerprint,
+                |response: &RevokeCertificateResponse| response.success.then(|| response.clone()),
+                revoke,
+            )
+            .await?;
+        Ok(Response::new(response))
     }
 
     async fn get_certificate_status(
@@ -191,18 +562,7 @@ impl CertAgent for CertAgentService {
             .get_certificate_status(&req.certificate_id)
             .await
         {
-            Ok(Some(cert_record)) => {
-                let response = GetCertificateStatusResponse {
-                    certificate_id: cert_record.certificate_id,
-                    status: cert_status_to_proto(&cert_record.status),
-                    expires_at: cert_record.expires_at,
-                    issued_at: cert_record.issued_at,
-                    common_name: cert_record.common_name,
-                    dns_names: cert_record.dns_names,
-                    metadata: cert_record.metadata,
-                };
-                Ok(Response::new(response))
-            }
+            Ok(Some(cert_record)) => Ok(Response::new(record_to_status_response(cert_record))),
             Ok(None) => {
                 warn!("Certificate not found: {}", req.certificate_id);
                 Err(Status::not_found(format!(
@@ -223,6 +583,39 @@ impl CertAgent for CertAgentService {
         }
     }
 
+    async fn get_certificate_by_serial(
+        &self,
+        request: Request<GetCertificateBySerialRequest>,
+    ) -> std::result::Result<Response<GetCertificateStatusResponse>, Status> {
+        let req = request.into_inner();
+
+        match self
+            .cert_manager
+            .get_certificate_by_serial(&req.serial_number)
+            .await
+        {
+            Ok(Some(cert_record)) => Ok(Response::new(record_to_status_response(cert_record))),
+            Ok(None) => {
+                warn!("Certificate not found for serial: {}", req.serial_number);
+                Err(Status::not_found(format!(
+                    "Certificate not found for serial: {}",
+                    req.serial_number
+                )))
+            }
+            Err(CertAgentError::InvalidRequest(reason)) => Err(Status::invalid_argument(reason)),
+            Err(e) => {
+                error!(
+                    "Failed to look up certificate by serial {}: {}",
+                    req.serial_number, e
+                );
+                Err(Status::internal(format!(
+                    "Failed to look up certificate by serial: {}",
+                    e
+                )))
+            }
+        }
+    }
+
     async fn list_certificates(
         &self,
         request: Request<ListCertificatesRequest>,
@@ -235,32 +628,49 @@ impl CertAgent for CertAgentService {
             Some(proto_to_cert_status(&req.status))
         };
 
+        let query = CertificateQuery {
+            status: status_filter,
+            common_name: non_empty(req.common_name),
+            common_name_prefix: non_empty(req.common_name_prefix),
+            dns_name: non_empty(req.dns_name),
+            ip_address: non_empty(req.ip_address),
+            metadata: req.metadata,
+            issuer_key_id: non_empty(req.issuer_key_id),
+            profile: non_empty(req.profile),
+            lineage_id: non_empty(req.lineage_id),
+            issued_after: non_zero(req.issued_after),
+            issued_before: non_zero(req.issued_before),
+            expires_after: non_zero(req.expires_after),
+            expires_before: non_zero(req.expires_before),
+            sort: match ListSortOrder::try_from(req.sort) {
+                Ok(ListSortOrder::IssuedDesc) => ListSort::IssuedDesc,
+                Ok(ListSortOrder::ExpiresAsc) => ListSort::ExpiresAsc,
+                Ok(ListSortOrder::ExpiresDesc) => ListSort::ExpiresDesc,
+                _ => ListSort::IssuedAsc,
+            },
+        };
+        let page_size = match req.page_size {
+            size if size <= 0 => DEFAULT_PAGE_SIZE,
+            size => (size as usize).min(MAX_PAGE_SIZE),
+        };
+
         match self
             .cert_manager
-            .list_certificates(status_filter.as_deref())
+            .list_certificates_page(&query, page_size, non_empty(req.page_token).as_deref())
             .await
         {
-            Ok(certificates) => {
-                let cert_infos: Vec<CertificateInfo> = certificates
-                    .into_iter()
-                    .map(|cert| CertificateInfo {
-                        certificate_id: cert.certificate_id,
-                        common_name: cert.common_name,
-                        dns_names: cert.dns_names,
-                        status: cert_status_to_proto(&cert.status),
-                        expires_at: cert.expires_at,
-                        issued_at: cert.issued_at,
-                        metadata: cert.metadata,
-                    })
-                    .collect();
+            Ok(page) => {
+                let cert_infos: Vec<CertificateInfo> =
+                    page.certificates.into_iter().map(record_to_info).collect();
 
                 let response = ListCertificatesResponse {
                     certificates: cert_infos,
-                    next_page_token: String::new(), // TODO: Implement pagination
+                    next_page_token: page.next_page_token.unwrap_or_default(),
                 };
 
                 Ok(Response::new(response))
             }
+            Err(CertAgentError::InvalidRequest(reason)) => Err(Status::invalid_argument(reason)),
             Err(e) => {
                 error!("Failed to list certificates: {}", e);
                 Err(Status::internal(format!(
@@ -271,6 +681,45 @@ impl CertAgent for CertAgentService {
         }
     }
 
+    async fn get_certificate_lineage(
+        &self,
+        request: Request<GetCertificateLineageRequest>,
+    ) -> std::result::Result<Response<GetCertificateLineageResponse>, Status> {
+        let req = request.into_inner();
+
+        let id = match (non_empty(req.certificate_id), non_empty(req.lineage_id)) {
+            (Some(id), None) | (None, Some(id)) => id,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Set exactly one of certificate_id and lineage_id",
+                ))
+            }
+        };
+
+        match self.cert_manager.get_certificate_lineage(&id).await {
+            Ok(lineage) if lineage.is_empty() => {
+                Err(Status::not_found(format!("Certificate not found: {}", id)))
+            }
+            Ok(lineage) => {
+                let current = current_certificate(&lineage).cloned().map(record_to_info);
+                let response = GetCertificateLineageResponse {
+                    lineage_id: lineage[0].lineage().to_string(),
+                    certificates: lineage.into_iter().map(record_to_info).collect(),
+                    current,
+                };
+
+                Ok(Response::new(response))
+            }
+            Err(e) => {
+                error!("Failed to get certificate lineage {}: {}", id, e);
+                Err(Status::internal(format!(
+                    "Failed to get certificate lineage: {}",
+                    e
+                )))
+            }
+        }
+    }
+
     type WatchCertificatesStream = ReceiverStream<std::result::Result<CertificateEvent, Status>>;
 
     async fn watch_certificates(
@@ -284,18 +733,53 @@ impl CertAgent for CertAgentService {
             req.certificate_ids.len()
         );
 
+        // Subscribe before answering so no event published from here on is missed
+        let events = self.store.subscribe_events().await.map_err(|e| {
+            error!("Failed to subscribe to certificate events: {}", e);
+            Status::unavailable(format!("Failed to subscribe to certificate events: {}", e))
+        })?;
+
         let (tx, rx) = tokio::sync::mpsc::channel(100);
         let cert_manager = self.cert_manager.clone();
-        let _redis = self.redis.clone();
         let certificate_ids = req.certificate_ids;
         let check_interval = req.check_interval_seconds;
 
         tokio::spawn(async move {
             let mut interval =
                 tokio::time::interval(tokio::time::Duration::from_secs(check_interval as u64));
+            tokio::pin!(events);
 
             loop {
-                interval.tick().await;
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    event = events.next() => {
+                        let Some((event, data)) = event else {
+                            warn!("Certificate event subscription closed, ending watch stream");
+                            return;
+                        };
+                        let Some(event_type) = event_type(&event) else {
+                            continue;
+                        };
+                        // Revocation events carry the reason after the ID
+                        let certificate_id = data.split(':').next().unwrap_or_default();
+                        if !certificate_ids.is_empty()
+                            && !certificate_ids.iter().any(|id| id == certificate_id)
+                        {
+                            continue;
+                        }
+
+                        let event = CertificateEvent {
+                            certificate_id: certificate_id.to_string(),
+                            event_type: event_type as i32,
+                            message: format!("Certificate {}", event),
+                            timestamp: chrono::Utc::now().timestamp(),
+                        };
+                        if tx.send(Ok(event)).await.is_err() {
+                            return; // Client disconnected
+                        }
+                        continue;
+                    }
+                }
 
                 // Get expiring certificates
                 match cert_manager.get_expiring_certificates().await {
@@ -341,6 +825,195 @@ impl CertAgent for CertAgentService {
 
         Ok(Response::new(ReceiverStream::new(rx)))
     }
+
+    async fn stage_ca_rotation(
+        &self,
+        request: Request<StageCaRotationRequest>,
+    ) -> std::result::Result<Response<CaRotationStatusResponse>, Status> {
+        let req = request.into_inner();
+
+        info!("Staging CA rotation");
+
+        self.rotation
+            .stage(
+                non_empty(req.ca_cert_path),
+                non_empty(req.ca_key_path),
+                non_empty(req.ca_chain_path),
+            )
+            .await
+            .map(|status| Response::new(rotation_status_to_proto(status)))
+            .map_err(|e| rotation_error("stage CA rotation", e))
+    }
+
+    async fn activate_ca_rotation(
+        &self,
+        _request: Request<ActivateCaRotationRequest>,
+    ) -> std::result::Result<Response<CaRotationStatusResponse>, Status> {
+        info!("Activating CA rotation");
+
+        self.rotation
+            .activate()
+            .await
+            .map(|status| Response::new(rotation_status_to_proto(status)))
+            .map_err(|e| rotation_error("activate CA rotation", e))
+    }
+
+    async fn retire_ca_rotation(
+        &self,
+        request: Request<RetireCaRotationRequest>,
+    ) -> std::result::Result<Response<CaRotationStatusResponse>, Status> {
+        let req = request.into_inner();
+
+        info!("Retiring CA rotation (force: {})", req.force);
+
+        self.rotation
+            .retire(req.force)
+            .await
+            .map(|status| Response::new(rotation_status_to_proto(status)))
+            .map_err(|e| rotation_error("retire CA rotation", e))
+    }
+
+    async fn get_ca_rotation_status(
+        &self,
+        _request: Request<GetCaRotationStatusRequest>,
+    ) -> std::result::Result<Response<CaRotationStatusResponse>, Status> {
+        self.rotation
+            .status()
+            .await
+            .map(|status| Response::new(rotation_status_to_proto(status)))
+            .map_err(|e| rotation_error("get CA rotation status", e))
+    }
+}
+
+fn rotation_status_to_proto(status: RotationStatus) -> CaRotationStatusResponse {
+    CaRotationStatusResponse {
+        phase: rotation_phase_to_proto(&status.phase),
+        active_ca_fingerprint: status.active_ca_fingerprint,
+        staged_ca_fingerprint: status.staged_ca_fingerprint.unwrap_or_default(),
+        previous_ca_fingerprint: status.previous_ca_fingerprint.unwrap_or_default(),
+        trust_bundle_pem: status.trust_bundle_pem,
+        remaining_certificates: status.remaining_certificates as i64,
+        staged_at: status.staged_at.unwrap_or_default(),
+        activated_at: status.activated_at.unwrap_or_default(),
+        reissue_deadline: status.reissue_deadline.unwrap_or_default(),
+    }
+}
+
+fn rotation_error(action: &str, error: CertAgentError) -> Status {
+    match error {
+        CertAgentError::InvalidState(reason) => {
+            warn!("Cannot {}: {}", action, reason);
+            Status::failed_precondition(reason)
+        }
+        CertAgentError::InvalidRequest(reason) => {
+            warn!("Cannot {}: {}", action, reason);
+            Status::invalid_argument(reason)
+        }
+        e => {
+            error!("Failed to {}: {}", action, e);
+            Status::internal(format!("Failed to {}: {}", action, e))
+        }
+    }
+}
+
+fn record_to_status_response(cert_record: CertificateRecord) -> GetCertificateStatusResponse {
+    GetCertificateStatusResponse {
+        lineage_id: cert_record.lineage().to_string(),
+        certificate_id: cert_record.certificate_id,
+        status: cert_status_to_proto(&cert_record.status),
+        expires_at: cert_record.expires_at,
+        issued_at: cert_record.issued_at,
+        common_name: cert_record.common_name,
+        dns_names: cert_record.dns_names,
+        metadata: cert_record.metadata,
+        signature_algorithm: cert_record.signature_algorithm,
+        serial_number: cert_record.serial_number,
+        fingerprint_sha256: cert_record.fingerprint_sha256,
+        profile: cert_record.profile,
+        owner: cert_record.owner,
+        issuer_key_id: cert_record.issuer_key_id,
+        predecessor_id: cert_record.predecessor_id.unwrap_or_default(),
+        successor_id: cert_record.successor_id.unwrap_or_default(),
+    }
+}
+
+fn record_to_info(cert_record: CertificateRecord) -> CertificateInfo {
+    CertificateInfo {
+        lineage_id: cert_record.lineage().to_string(),
+        certificate_id: cert_record.certificate_id,
+        common_name: cert_record.common_name,
+        dns_names: cert_record.dns_names,
+        status: cert_status_to_proto(&cert_record.status),
+        expires_at: cert_record.expires_at,
+        issued_at: cert_record.issued_at,
+        metadata: cert_record.metadata,
+        serial_number: cert_record.serial_number,
+        profile: cert_record.profile,
+        owner: cert_record.owner,
+        issuer_key_id: cert_record.issuer_key_id,
+        predecessor_id: cert_record.predecessor_id.unwrap_or_default(),
+        successor_id: cert_record.successor_id.unwrap_or_default(),
+    }
+}
+
+fn owner_error(e: CertAgentError) -> Status {
+    match e {
+        CertAgentError::PermissionDenied(reason) => Status::permission_denied(reason),
+        CertAgentError::CertificateNotFound(id) => {
+            Status::not_found(format!("Certificate not found: {}", id))
+        }
+        e => Status::internal(format!("Failed to check certificate owner: {}", e)),
+    }
+}
+
+/// Maps failures to claim an idempotency key to a status.
+fn idempotency_error(e: CertAgentError) -> Status {
+    match e {
+        CertAgentError::InvalidRequest(reason) => Status::invalid_argument(reason),
+        CertAgentError::InvalidState(reason) => Status::failed_precondition(reason),
+        CertAgentError::InProgress(reason) => Status::aborted(reason),
+        e => Status::unavailable(format!("Failed to check idempotency key: {}", e)),
+    }
+}
+
+/// The idempotency key of a request: its `idempotency_key` field or, when
+/// that is empty, the `idempotency-key` metadata header.
+fn idempotency_key<T>(request: &Request<T>, field: &str) -> crate::error::Result<Option<String>> {
+    let header = request
+        .metadata()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|value| {
+            value.to_str().map(str::to_string).map_err(|_| {
+                CertAgentError::InvalidRequest(
+                    "Idempotency key header is not valid ASCII".to_string(),
+                )
+            })
+        })
+        .transpose()?
+        .and_then(non_empty);
+
+    match (non_empty(field.to_string()), header) {
+        (Some(field), Some(header)) if field != header => Err(CertAgentError::InvalidRequest(
+            "Idempotency key field and header differ".to_string(),
+        )),
+        (field, header) => Ok(field.or(header)),
+    }
+}
+
+fn non_zero(value: i64) -> Option<i64> {
+    if value == 0 {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
 }
 
 // Helper functions for status conversion
@@ -350,16 +1023,41 @@ fn cert_status_to_proto(status: &str) -> i32 {
         "expired" => CertificateStatus::Expired as i32,
         "revoked" => CertificateStatus::Revoked as i32,
         "pending" => CertificateStatus::Pending as i32,
+        "superseded" => CertificateStatus::Superseded as i32,
         _ => CertificateStatus::Unspecified as i32,
     }
 }
 
+/// Watch stream event type for an event published by the store, if
+/// it is one clients can watch for.
+fn event_type(event: &str) -> Option<CertificateEventType> {
+    match event {
+        "issued" => Some(CertificateEventType::Issued),
+        "renewed" | "auto_renewed" | "ca_rotation_reissued" => Some(CertificateEventType::Renewed),
+        "revoked" => Some(CertificateEventType::Revoked),
+        "expired" => Some(CertificateEventType::Expired),
+        "renewal_required" => Some(CertificateEventType::Expiring),
+        "superseded" => Some(CertificateEventType::Superseded),
+        _ => None,
+    }
+}
+
+fn rotation_phase_to_proto(phase: &str) -> i32 {
+    match phase {
+        "idle" => CaRotationPhase::Idle as i32,
+        "staged" => CaRotationPhase::Staged as i32,
+        "reissuing" => CaRotationPhase::Reissuing as i32,
+        _ => CaRotationPhase::Unspecified as i32,
+    }
+}
+
 fn proto_to_cert_status(status: &i32) -> String {
     match *status {
         x if x == CertificateStatus::Active as i32 => "active".to_string(),
         x if x == CertificateStatus::Expired as i32 => "expired".to_string(),
         x if x == CertificateStatus::Revoked as i32 => "revoked".to_string(),
         x if x == CertificateStatus::Pending as i32 => "pending".to_string(),
+        x if x == CertificateStatus::Superseded as i32 => "superseded".to_string(),
         _ => "unspecified".to_string(),
     }
 }
@@ -369,7 +1067,92 @@ impl Clone for CertAgentService {
     fn clone(&self) -> Self {
         Self {
             cert_manager: self.cert_manager.clone(),
-            redis: self.redis.clone(),
+            store: self.store.clone(),
+            rotation: self.rotation.clone(),
+            idempotency: self.idempotency.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::tests::test_manager_with;
+    use crate::config::{IdempotencyConfig, RotationConfig};
+    use tempfile::TempDir;
+    use tonic::Code;
+
+    async fn service(persist_leaf_keys: bool) -> (CertAgentService, TempDir) {
+        let (cert_manager, store, dir) = test_manager_with(|config| {
+            config.certificate.key_storage.persist_leaf_keys = persist_leaf_keys;
+        })
+        .await;
+        let store: Arc<dyn CertificateStore> = store;
+        let rotation = CaRotation::new(
+            cert_manager.clone(),
+            store.clone(),
+            RotationConfig::default(),
+        );
+        let idempotency = Idempotency::new(store.clone(), &IdempotencyConfig::default());
+        let service = CertAgentService::new(cert_manager, store, rotation, idempotency);
+        (service, dir)
+    }
+
+    fn issue_request(idempotency_key: &str) -> Request<IssueCertificateRequest> {
+        Request::new(IssueCertificateRequest {
+            common_name: "api.example.com".to_string(),
+            dns_names: vec!["api.example.com".to_string()],
+            idempotency_key: idempotency_key.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn replays_issuance_with_the_same_idempotency_key() {
+        let (service, _dir) = service(true).await;
+
+        let first = service
+            .issue_certificate(issue_request("retry-1"))
+            .await
+            .unwrap()
+            .into_inner();
+        let retry = service
+            .issue_certificate(issue_request("retry-1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(retry, first);
+        assert!(!retry.private_key_pem.is_empty());
+
+        let unkeyed = service
+            .issue_certificate(issue_request(""))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_ne!(unkeyed.certificate_id, first.certificate_id);
+    }
+
+    #[tokio::test]
+    async fn refuses_idempotency_keys_when_leaf_keys_are_not_kept() {
+        let (service, _dir) = service(false).await;
+
+        let status = service
+            .issue_certificate(issue_request("retry-1"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(service
+            .store
+            .list_certificates(None)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let issued = service
+            .issue_certificate(issue_request(""))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!issued.private_key_pem.is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::certificate::CertificateManager;
+use crate::error::{CertAgentError, Result};
+use crate::ocsp::OcspResponder;
+use crate::rotation::CaRotation;
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use base64::{engine::general_purpose, Engine as _};
+use tracing::{error, info};
+
+/// Plain HTTP endpoint for data that relying parties fetch without gRPC:
+/// the CRL and the OCSP responder referenced from issued certificates, and
+/// the CA trust bundle.
+#[derive(Debug, Clone)]
+pub struct RevocationHttpServer {
+    cert_manager: CertificateManager,
+    ocsp_responder: OcspResponder,
+    rotation: CaRotation,
+}
+
+impl RevocationHttpServer {
+    pub fn new(
+        cert_manager: CertificateManager,
+        ocsp_responder: OcspResponder,
+        rotation: CaRotation,
+    ) -> Self {
+        Self {
+            cert_manager,
+            ocsp_responder,
+            rotation,
+        }
+    }
+
+    pub async fn start(&self, bind_address: String) -> Result<()> {
+        let app = Router::new()
+            .route("/crl", get(get_active_crl))
+            .route("/crl/:key_id", get(get_crl))
+            .route("/ocsp", post(post_ocsp))
+            .route("/ocsp/*request", get(get_ocsp))
+            .route("/ca-bundle", get(get_ca_bundle))
+            .with_state(self.clone());
+
+        let listener = tokio::net::TcpListener::bind(&bind_address).await?;
+
+        info!("Starting revocation HTTP server on {}", bind_address);
+
+        axum::serve(listener, app)
+            .await
+            .map_err(|e| CertAgentError::Internal(format!("HTTP server error: {}", e)))?;
+
+        Ok(())
+    }
+}
+
+// The active CA's CRL, for certificates issued before each CA had its own
+async fn get_active_crl(State(server): State<RevocationHttpServer>) -> Response {
+    crl_response(server.cert_manager.current_crl(None).await)
+}
+
+// Issued certificates point at the CRL of their CA by its key identifier
+async fn get_crl(
+    State(server): State<RevocationHttpServer>,
+    Path(key_id): Path<String>,
+) -> Response {
+    crl_response(server.cert_manager.current_crl(Some(&key_id)).await)
+}
+
+fn crl_response(crl: Result<Option<Vec<u8>>>) -> Response {
+    match crl {
+        Ok(Some(crl)) => ([(header::CONTENT_TYPE, "application/pkix-crl")], crl).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Unknown CA").into_response(),
+        Err(e) => {
+            error!("Failed to serve CRL: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "CRL unavailable").into_response()
+        }
+    }
+}
+
+// Includes both CAs while a rotation is in progress
+async fn get_ca_bundle(State(server): State<RevocationHttpServer>) -> Response {
+    match server.rotation.trust_bundle_pem().await {
+        Ok(bundle) => (
+            [(header::CONTENT_TYPE, "application/x-pem-file")],
+            bundle.concat(),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to serve CA bundle: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "CA bundle unavailable").into_response()
+        }
+    }
+}
+
+async fn post_ocsp(State(server): State<RevocationHttpServer>, body: Bytes) -> Response {
+    ocsp_response(server.ocsp_responder.respond(&body).await)
+}
+
+// RFC 6960 appendix A.1: GET carries the base64 DER request in the path
+async fn get_ocsp(
+    State(server): State<RevocationHttpServer>,
+    Path(request): Path<String>,
+) -> Response {
+    let request_der = match general_purpose::STANDARD
+        .decode(&request)
+        .or_else(|_| general_purpose::URL_SAFE.decode(&request))
+    {
+        Ok(der) => der,
+        Err(_) => {
+            return (StatusCode::BAD_REQUEST, "Invalid OCSP request encoding").into_response()
+        }
+    };
+
+    ocsp_response(server.ocsp_responder.respond(&request_der).await)
+}
+
+fn ocsp_response(body: Vec<u8>) -> Response {
+    ([(header::CONTENT_TYPE, "application/ocsp-response")], body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::tests::{request, test_manager};
+    use crate::config::{RevocationConfig, RotationConfig};
+    use crate::ocsp::tests::{basic_response, cert_id, ocsp_request};
+    use crate::store::CertificateStore;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn decodes_get_requests_in_either_base64_alphabet() {
+        let (cert_manager, store, _dir) = test_manager().await;
+        let store: Arc<dyn CertificateStore> = store;
+        let ocsp_responder =
+            OcspResponder::new(&cert_manager, &RevocationConfig::default(), store.clone())
+                .await
+                .unwrap();
+        let rotation = CaRotation::new(cert_manager.clone(), store, RotationConfig::default());
+        let server = RevocationHttpServer::new(cert_manager.clone(), ocsp_responder, rotation);
+        let issued = cert_manager
+            .issue_certificate(request("api.example.com"))
+            .await
+            .unwrap();
+        let ca = cert_manager.ca();
+        let request_der = ocsp_request(vec![cert_id(&ca.cert, &issued)], Some(b"nonce"));
+
+        for encoded in [
+            general_purpose::STANDARD.encode(&request_der),
+            general_purpose::URL_SAFE.encode(&request_der),
+        ] {
+            let response = get_ocsp(State(server.clone()), Path(encoded)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "application/ocsp-response"
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let basic = basic_response(&body, &ca.cert);
+            assert_eq!(basic.tbs_response_data.responses.len(), 1);
+        }
+
+        let response = get_ocsp(State(server), Path("not base64!".to_string())).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
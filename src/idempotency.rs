@@ -0,0 +1,222 @@
+use crate::config::IdempotencyConfig;
+use crate::error::{CertAgentError, Result};
+use crate::store::{CertificateStore, IdempotencyRecord};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine as _,
+};
+use openssl::sha::Sha256;
+use std::sync::Arc;
+
+/// gRPC metadata header carrying the idempotency key of requests that do
+/// not set the `idempotency_key` field.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Longest idempotency key accepted.
+const MAX_KEY_LENGTH: usize = 255;
+
+/// How long a key stays claimed by a request that never finished, e.g.
+/// because the agent stopped while handling it.
+const PENDING_TTL_SECONDS: u64 = 300;
+
+/// Remembers the responses to requests made under an idempotency key, so
+/// a client retrying after a timeout gets the original response instead of
+/// a second certificate.
+#[derive(Debug, Clone)]
+pub struct Idempotency {
+    store: Arc<dyn CertificateStore>,
+    ttl_seconds: u64,
+}
+
+/// Outcome of claiming an idempotency key.
+#[derive(Debug)]
+pub enum Claim {
+    /// First use of the key: carry the request out, then `complete` or
+    /// `release` the claim.
+    New(PendingRequest),
+    /// Retry of a completed request, answered with its encoded response.
+    Replay(Vec<u8>),
+}
+
+/// A request holding its idempotency key until it completes.
+#[derive(Debug)]
+pub struct PendingRequest {
+    key: String,
+    fingerprint: String,
+}
+
+impl Idempotency {
+    pub fn new(store: Arc<dyn CertificateStore>, config: &IdempotencyConfig) -> Self {
+        Self {
+            store,
+            ttl_seconds: config.ttl_seconds,
+        }
+    }
+
+    /// Claims `key` for a `method` request whose parameters encode to
+    /// `request`. Keys are scoped to the caller and method, so different
+    /// callers never see each other's responses. Reusing a key with other
+    /// parameters, or while the first request is still running, is refused.
+    pub async fn claim(
+        &self,
+        caller: &str,
+        method: &str,
+        key: &str,
+        request: &[u8],
+    ) -> Result<Claim> {
+        if key.len() > MAX_KEY_LENGTH {
+            return Err(CertAgentError::InvalidRequest(format!(
+                "Idempotency key is longer than {} bytes",
+                MAX_KEY_LENGTH
+            )));
+        }
+
+        let pending = PendingRequest {
+            key: digest(&[caller.as_bytes(), method.as_bytes(), key.as_bytes()]),
+            fingerprint: digest(&[request]),
+        };
+        let record = IdempotencyRecord {
+            fingerprint: pending.fingerprint.clone(),
+            response: None,
+        };
+        let existing = self
+            .store
+            .claim_idempotency_key(
+                &pending.key,
+                &record,
+                PENDING_TTL_SECONDS.min(self.ttl_seconds),
+            )
+            .await?;
+
+        match existing {
+            None => Ok(Claim::New(pending)),
+            Some(existing) if existing.fingerprint != pending.fingerprint => {
+                Err(CertAgentError::InvalidState(format!(
+                    "Idempotency key {} was already used for a {} request with other parameters",
+                    key, method
+                )))
+            }
+            Some(IdempotencyRecord { response: None, .. }) => {
+                Err(CertAgentError::InProgress(format!(
+                    "A {} request with idempotency key {} is still in progress",
+                    method, key
+                )))
+            }
+            Some(IdempotencyRecord {
+                response: Some(response),
+                ..
+            }) => STANDARD.decode(response).map(Claim::Replay).map_err(|e| {
+                CertAgentError::Internal(format!("Stored idempotent response is corrupt: {}", e))
+            }),
+        }
+    }
+
+    /// Stores the response to a claimed request for retries to receive.
+    pub async fn complete(&self, pending: PendingRequest, response: &[u8]) -> Result<()> {
+        let record = IdempotencyRecord {
+            fingerprint: pending.fingerprint,
+            response: Some(STANDARD.encode(response)),
+        };
+        self.store
+            .store_idempotency_record(&pending.key, &record, self.ttl_seconds)
+            .await
+    }
+
+    /// Frees the key of a request that failed, so a retry carries it out.
+    pub async fn release(&self, pending: PendingRequest) -> Result<()> {
+        self.store.release_idempotency_key(&pending.key).await
+    }
+}
+
+/// SHA-256 over `parts`, each prefixed with its length so that the parts
+/// cannot run into each other.
+fn digest(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(&(part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    URL_SAFE_NO_PAD.encode(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn idempotency() -> Idempotency {
+        Idempotency::new(Arc::new(MemoryStore::new(0)), &IdempotencyConfig::default())
+    }
+
+    #[tokio::test]
+    async fn replays_completed_requests() {
+        let idempotency = idempotency();
+
+        let Claim::New(pending) = idempotency
+            .claim("ci", "IssueCertificate", "retry-1", b"request")
+            .await
+            .unwrap()
+        else {
+            panic!("first use of the key was not new");
+        };
+        idempotency.complete(pending, b"response").await.unwrap();
+
+        match idempotency
+            .claim("ci", "IssueCertificate", "retry-1", b"request")
+            .await
+            .unwrap()
+        {
+            Claim::Replay(response) => assert_eq!(response, b"response"),
+            Claim::New(_) => panic!("completed request was carried out again"),
+        }
+
+        // Same key, other parameters
+        assert!(matches!(
+            idempotency
+                .claim("ci", "IssueCertificate", "retry-1", b"other request")
+                .await,
+            Err(CertAgentError::InvalidState(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn scopes_keys_and_frees_failed_requests() {
+        let idempotency = idempotency();
+
+        let Claim::New(pending) = idempotency
+            .claim("ci", "RenewCertificate", "retry-1", b"request")
+            .await
+            .unwrap()
+        else {
+            panic!("first use of the key was not new");
+        };
+        assert!(matches!(
+            idempotency
+                .claim("ci", "RenewCertificate", "retry-1", b"request")
+                .await,
+            Err(CertAgentError::InProgress(_))
+        ));
+
+        // Other callers and methods have keys of their own
+        assert!(matches!(
+            idempotency
+                .claim("deploy-bot", "RenewCertificate", "retry-1", b"request")
+                .await,
+            Ok(Claim::New(_))
+        ));
+        assert!(matches!(
+            idempotency
+                .claim("ci", "RevokeCertificate", "retry-1", b"request")
+                .await,
+            Ok(Claim::New(_))
+        ));
+
+        idempotency.release(pending).await.unwrap();
+        assert!(matches!(
+            idempotency
+                .claim("ci", "RenewCertificate", "retry-1", b"request")
+                .await,
+            Ok(Claim::New(_))
+        ));
+    }
+}
@@ -1,17 +1,33 @@
+mod auth;
+mod ca;
 mod certificate;
 mod config;
+mod crl;
 mod error;
 mod grpc;
+mod http;
+mod idempotency;
+mod keys;
+mod ocsp;
+mod policy;
+mod profile;
 mod redis_client;
+mod rotation;
+mod store;
+mod tls;
 mod watcher;
 
 use anyhow::Result;
 use clap::Parser;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use config::Config;
+use crl::CrlPublisher;
 use grpc::CertAgentService;
+use http::RevocationHttpServer;
+use ocsp::OcspResponder;
+use rotation::CaRotation;
 use watcher::CertificateWatcher;
 
 #[derive(Parser)]
@@ -23,6 +39,9 @@ struct Args {
 
     #[arg(long, default_value = "info")]
     log_level: String,
+
+    #[command(subcommand)]
+    command: Option<ca::CaCommand>,
 }
 
 #[tokio::main]
@@ -38,24 +57,52 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    info!("Starting cert-agent service...");
-
     // Load configuration
     let config = Config::load(&args.config)?;
     info!("Configuration loaded from: {}", args.config);
 
-    // Initialize Redis client
-    let redis_client = redis_client::RedisClient::new(&config.redis.url).await?;
-    info!("Connected to Redis at: {}", config.redis.url);
+    // Offline CA maintenance runs instead of the service
+    if let Some(command) = args.command {
+        ca::run(command, &config.certificate).await?;
+        return Ok(());
+    }
+
+    info!("Starting cert-agent service...");
+
+    // Open the certificate store
+    let store = store::open(&config).await?;
 
     // Initialize certificate manager
-    let cert_manager =
-        certificate::CertificateManager::new(&config.certificate, redis_client.clone()).await?;
+    let cert_manager = certificate::CertificateManager::new(
+        &config.certificate,
+        &config.revocation,
+        &config.policy,
+        store.clone(),
+    )
+    .await?;
+
+    // Clean up after operations a crash interrupted
+    if let Err(e) = cert_manager.reconcile().await {
+        error!("Failed to reconcile certificate storage: {}", e);
+    }
+
+    let rotation = CaRotation::new(cert_manager.clone(), store.clone(), config.rotation.clone());
+
+    // Load (or bootstrap) the gRPC serving certificate
+    let grpc_tls = match &config.grpc.tls {
+        Some(tls) => Some(tls::ServingCertificate::load(tls, &cert_manager).await?),
+        None => {
+            warn!("gRPC TLS is not configured; the API, including private keys, is served in plaintext");
+            None
+        }
+    };
 
     // Start certificate watcher
     let watcher = CertificateWatcher::new(
         cert_manager.clone(),
-        redis_client.clone(),
+        store.clone(),
+        rotation.clone(),
+        grpc_tls.clone(),
         config.watcher.clone(),
     );
     let watcher_handle = tokio::spawn(async move {
@@ -64,13 +111,50 @@ async fn main() -> Result<()> {
         }
     });
 
+    // Start CRL publisher
+    let crl_publisher = CrlPublisher::new(cert_manager.clone(), config.revocation.clone());
+    let crl_handle = tokio::spawn(async move {
+        if let Err(e) = crl_publisher.start().await {
+            error!("CRL publisher error: {}", e);
+        }
+    });
+
+    // Start revocation HTTP endpoint
+    let http_handle = match config.revocation.http_bind_address.clone() {
+        Some(bind_address) => {
+            let ocsp_responder =
+                OcspResponder::new(&cert_manager, &config.revocation, store.clone()).await?;
+            let http_server =
+                RevocationHttpServer::new(cert_manager.clone(), ocsp_responder, rotation.clone());
+            tokio::spawn(async move {
+                if let Err(e) = http_server.start(bind_address).await {
+                    error!("Revocation HTTP server error: {}", e);
+                }
+            })
+        }
+        None => tokio::spawn(std::future::pending()),
+    };
+
+    let auth = auth::AuthLayer::new(
+        &config.auth,
+        store.clone(),
+        config.revocation.renewal_overlap_seconds,
+    )?;
+    if !config.auth.enabled {
+        warn!("gRPC authentication is disabled; every caller may use every RPC");
+    }
+
     // Initialize gRPC service
-    let grpc_service = CertAgentService::new(cert_manager, redis_client);
+    let idempotency = idempotency::Idempotency::new(store.clone(), &config.idempotency);
+    let grpc_service = CertAgentService::new(cert_manager, store, rotation, idempotency);
 
     // Start gRPC server
     let bind_address = config.grpc.bind_address.clone();
     let grpc_handle = tokio::spawn(async move {
-        if let Err(e) = grpc_service.start(bind_address.clone()).await {
+        if let Err(e) = grpc_service
+            .start(bind_address.clone(), grpc_tls, auth)
+            .await
+        {
             error!("gRPC server error: {}", e);
         }
     });
@@ -90,6 +174,12 @@ async fn main() -> Result<()> {
         result = grpc_handle => {
             error!("gRPC server exited: {:?}", result);
         }
+        result = crl_handle => {
+            error!("CRL publisher exited: {:?}", result);
+        }
+        result = http_handle => {
+            error!("Revocation HTTP server exited: {:?}", result);
+        }
     }
 
     Ok(())
@@ -0,0 +1,730 @@
+use crate::certificate::{
+    sign_tbs, signature_algorithm_identifier, CertificateManager, SignatureAlgorithm,
+};
+use crate::config::RevocationConfig;
+use crate::crl::parse_reason;
+use crate::error::{CertAgentError, Result};
+use crate::store::{CertificateRecord, CertificateStore};
+use chrono::Utc;
+use der::{
+    asn1::{BitString, GeneralizedTime, Null, ObjectIdentifier, OctetString},
+    oid::db::{rfc5912, rfc6960},
+    Any, Choice, Decode, Encode, Enumerated, Sequence,
+};
+use openssl::{
+    bn::BigNum,
+    hash::{hash, MessageDigest},
+    pkey::{PKey, Private},
+    x509::X509,
+};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tracing::{info, warn};
+use x509_cert::{
+    ext::{
+        pkix::{CrlReason, ExtendedKeyUsage},
+        Extension, Extensions,
+    },
+    serial_number::SerialNumber,
+    spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned},
+    Certificate, Version,
+};
+
+// ASN.1 structures from RFC 6960 section 4, limited to what the responder uses.
+
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct OcspRequest {
+    pub tbs_request: TbsRequest,
+    #[asn1(context_specific = "0", tag_mode = "EXPLICIT", optional = "true")]
+    pub optional_signature: Option<Any>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct TbsRequest {
+    #[asn1(
+        context_specific = "0",
+        tag_mode = "EXPLICIT",
+        default = "Default::default"
+    )]
+    pub version: Version,
+    #[asn1(context_specific = "1", tag_mode = "EXPLICIT", optional = "true")]
+    pub requestor_name: Option<Any>,
+    pub request_list: Vec<Request>,
+    #[asn1(context_specific = "2", tag_mode = "EXPLICIT", optional = "true")]
+    pub request_extensions: Option<Extensions>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct Request {
+    pub req_cert: CertId,
+    #[asn1(context_specific = "0", tag_mode = "EXPLICIT", optional = "true")]
+    pub single_request_extensions: Option<Extensions>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct CertId {
+    pub hash_algorithm: AlgorithmIdentifierOwned,
+    pub issuer_name_hash: OctetString,
+    pub issuer_key_hash: OctetString,
+    pub serial_number: SerialNumber,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Enumerated)]
+#[repr(u32)]
+pub enum OcspResponseStatus {
+    Successful = 0,
+    MalformedRequest = 1,
+    InternalError = 2,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct OcspResponse {
+    pub response_status: OcspResponseStatus,
+    #[asn1(context_specific = "0", tag_mode = "EXPLICIT", optional = "true")]
+    pub response_bytes: Option<ResponseBytes>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct ResponseBytes {
+    pub response_type: ObjectIdentifier,
+    pub response: OctetString,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct BasicOcspResponse {
+    pub tbs_response_data: ResponseData,
+    pub signature_algorithm: AlgorithmIdentifierOwned,
+    pub signature: BitString,
+    #[asn1(context_specific = "0", tag_mode = "EXPLICIT", optional = "true")]
+    pub certs: Option<Vec<Certificate>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct ResponseData {
+    #[asn1(
+        context_specific = "0",
+        tag_mode = "EXPLICIT",
+        default = "Default::default"
+    )]
+    pub version: Version,
+    pub responder_id: ResponderId,
+    pub produced_at: GeneralizedTime,
+    pub responses: Vec<SingleResponse>,
+    #[asn1(context_specific = "1", tag_mode = "EXPLICIT", optional = "true")]
+    pub response_extensions: Option<Extensions>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Choice)]
+pub enum ResponderId {
+    #[asn1(context_specific = "2", tag_mode = "EXPLICIT", constructed = "true")]
+    ByKey(OctetString),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct SingleResponse {
+    pub cert_id: CertId,
+    pub cert_status: CertStatus,
+    pub this_update: GeneralizedTime,
+    #[asn1(context_specific = "0", tag_mode = "EXPLICIT", optional = "true")]
+    pub next_update: Option<GeneralizedTime>,
+    #[asn1(context_specific = "1", tag_mode = "EXPLICIT", optional = "true")]
+    pub single_extensions: Option<Extensions>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Choice)]
+pub enum CertStatus {
+    #[asn1(context_specific = "0", tag_mode = "IMPLICIT")]
+    Good(Null),
+    #[asn1(context_specific = "1", tag_mode = "IMPLICIT", constructed = "true")]
+    Revoked(RevokedInfo),
+    #[asn1(context_specific = "2", tag_mode = "IMPLICIT")]
+    Unknown(Null),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct RevokedInfo {
+    pub revocation_time: GeneralizedTime,
+    #[asn1(context_specific = "0", tag_mode = "EXPLICIT", optional = "true")]
+    pub revocation_reason: Option<CrlReason>,
+}
+
+/// RFC 6960 responder answering status queries for certificates issued by
+/// the agent's CA, and during a CA rotation by the CA it replaced, signed
+/// either by the issuing CA itself or by a delegated OCSP signing
+/// certificate that CA issued.
+#[derive(Debug, Clone)]
+pub struct OcspResponder {
+    store: Arc<dyn CertificateStore>,
+    cert_manager: CertificateManager,
+    /// Delegated responder certificate and key; the certificate is included
+    /// in every response signed with it
+    delegated_signer: Option<(X509, PKey<Private>)>,
+    signature_algorithm: SignatureAlgorithm,
+    validity_seconds: u64,
+    renewal_overlap_seconds: u64,
+}
+
+impl OcspResponder {
+    pub async fn new(
+        cert_manager: &CertificateManager,
+        config: &RevocationConfig,
+        store: Arc<dyn CertificateStore>,
+    ) -> Result<Self> {
+        let mut responder = Self {
+            store,
+            cert_manager: cert_manager.clone(),
+            delegated_signer: None,
+            signature_algorithm: cert_manager.signature_algorithm(),
+            validity_seconds: config.ocsp_validity_seconds,
+            renewal_overlap_seconds: config.renewal_overlap_seconds,
+        };
+
+        responder.delegated_signer =
+            match (&config.ocsp_signer_cert_path, &config.ocsp_signer_key_path) {
+                (Some(cert_path), Some(key_path)) => {
+                    let cert = X509::from_pem(&fs::read(cert_path).await?)?;
+                    let key = cert_manager.keys().read(key_path).await?;
+
+                    // A delegated responder is only trusted if the CA issued it
+                    // for OCSP signing; mid-rotation that may be the previous CA
+                    let mut issuers = vec![cert_manager.ca()];
+                    issuers.extend(cert_manager.previous_ca().await?);
+                    let mut issued = false;
+                    for ca in &issuers {
+                        let ca_public_key = ca.cert.public_key()?;
+                        issued |= cert.verify(&ca_public_key)?;
+                    }
+                    if !issued {
+                        return Err(CertAgentError::Certificate(format!(
+                            "OCSP signing certificate {} was not issued by the CA",
+                            Path::new(cert_path).display()
+                        )));
+                    }
+                    if !has_ocsp_signing_usage(&cert)? {
+                        return Err(CertAgentError::Certificate(format!(
+                            "OCSP signing certificate {} lacks the OCSPSigning extended key usage",
+                            Path::new(cert_path).display()
+                        )));
+                    }
+                    if !cert.public_key()?.public_eq(&key) {
+                        return Err(CertAgentError::Certificate(format!(
+                            "OCSP signing key {} does not match certificate {}",
+                            key_path, cert_path
+                        )));
+                    }
+
+                    info!(
+                        "Using delegated OCSP signing certificate from {}",
+                        cert_path
+                    );
+                    Some((cert, key))
+                }
+                (None, None) => None,
+                _ => {
+                    return Err(CertAgentError::Certificate(
+                        "ocsp_signer_cert_path and ocsp_signer_key_path must be set together"
+                            .to_string(),
+                    ))
+                }
+            };
+
+        Ok(responder)
+    }
+
+    /// Answers a DER-encoded OCSP request with a DER-encoded OCSP response.
+    /// Protocol-level failures are reported inside the response, as RFC 6960
+    /// requires, rather than as errors.
+    pub async fn respond(&self, request_der: &[u8]) -> Vec<u8> {
+        let request = match OcspRequest::from_der(request_der) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Malformed OCSP request: {}", e);
+                return error_response(OcspResponseStatus::MalformedRequest);
+            }
+        };
+
+        match self.build_response(&request.tbs_request).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Failed to build OCSP response: {}", e);
+                error_response(OcspResponseStatus::InternalError)
+            }
+        }
+    }
+
+    async fn build_response(&self, request: &TbsRequest) -> Result<Vec<u8>> {
+        // Answer the whole request for one CA even if a rotation swaps it:
+        // the one that issued the first certificate asked about, which may
+        // be the CA a rotation replaced
+        let mut ca = self.cert_manager.ca();
+        if let (Some(first), Some(previous)) = (
+            request.request_list.first(),
+            self.cert_manager.previous_ca().await?,
+        ) {
+            if !issued_by_ca(&ca.cert, &first.req_cert)?
+                && issued_by_ca(&previous.cert, &first.req_cert)?
+            {
+                ca = previous;
+            }
+        }
+        let now = Utc::now().timestamp();
+        let this_update = generalized_time(now)?;
+        let next_update = generalized_time(now + self.validity_seconds as i64)?;
+
+        let mut responses = Vec::with_capacity(request.request_list.len());
+        for single in &request.request_list {
+            let cert_status = self.cert_status(&ca.cert, &single.req_cert).await?;
+            responses.push(SingleResponse {
+                cert_id: single.req_cert.clone(),
+                cert_status,
+                this_update,
+                next_update: Some(next_update),
+                single_extensions: None,
+            });
+        }
+
+        // Echo the client nonce to prevent replay of cached responses
+        let response_extensions = request.request_extensions.as_ref().and_then(|extensions| {
+            extensions
+                .iter()
+                .find(|ext| ext.extn_id == rfc6960::ID_PKIX_OCSP_NONCE)
+                .map(|nonce| {
+                    vec![Extension {
+                        extn_id: nonce.extn_id,
+                        critical: false,
+                        extn_value: nonce.extn_value.clone(),
+                    }]
+                })
+        });
+
+        // The delegated responder only speaks for the CA that issued it
+        let delegated = match &self.delegated_signer {
+            Some((cert, key)) => {
+                let ca_public_key = ca.cert.public_key()?;
+                cert.verify(&ca_public_key)?.then_some((cert, key))
+            }
+            None => None,
+        };
+        let (responder_cert, signer_key) = delegated.unwrap_or((&ca.cert, &ca.key));
+        let tbs_response_data = ResponseData {
+            version: Version::V1,
+            responder_id: ResponderId::ByKey(OctetString::new(public_key_hash(
+                responder_cert,
+                MessageDigest::sha1(),
+            )?)?),
+            produced_at: this_update,
+            responses,
+            response_extensions,
+        };
+
+        let algorithm = signature_algorithm_identifier(signer_key, self.signature_algorithm)?;
+        let signature = sign_tbs(
+            signer_key,
+            self.signature_algorithm,
+            &tbs_response_data.to_der()?,
+        )?;
+
+        let certs = match delegated {
+            Some((cert, _)) => Some(vec![Certificate::from_der(&cert.to_der()?)?]),
+            None => None,
+        };
+
+        let basic = BasicOcspResponse {
+            tbs_response_data,
+            signature_algorithm: algorithm,
+            signature: BitString::from_bytes(&signature)?,
+            certs,
+        };
+
+        let response = OcspResponse {
+            response_status: OcspResponseStatus::Successful,
+            response_bytes: Some(ResponseBytes {
+                response_type: rfc6960::ID_PKIX_OCSP_BASIC,
+                response: OctetString::new(basic.to_der()?)?,
+            }),
+        };
+
+        Ok(response.to_der()?)
+    }
+
+    async fn cert_status(&self, ca_cert: &X509, cert_id: &CertId) -> Result<CertStatus> {
+        if !issued_by_ca(ca_cert, cert_id)? {
+            return Ok(CertStatus::Unknown(Null));
+        }
+
+        let serial = BigNum::from_slice(cert_id.serial_number.as_bytes())?
+            .to_hex_str()?
+            .to_string();
+
+        let status = match self.store.get_certificate_by_serial(&serial).await? {
+            Some(record) if record.status == "revoked" => {
+                CertStatus::Revoked(revoked_info(&record)?)
+            }
+            // Superseded certificates turn revoked once the overlap ends
+            Some(record) => {
+                match record.retired(self.renewal_overlap_seconds, Utc::now().timestamp()) {
+                    Some(retired) => CertStatus::Revoked(revoked_info(&retired)?),
+                    None => CertStatus::Good(Null),
+                }
+            }
+            None => CertStatus::Unknown(Null),
+        };
+
+        Ok(status)
+    }
+}
+
+/// Checks the CertID issuer hashes against `ca_cert` using the hash
+/// algorithm the client chose.
+fn issued_by_ca(ca_cert: &X509, cert_id: &CertId) -> Result<bool> {
+    let digest = match cert_id.hash_algorithm.oid {
+        oid if oid == rfc5912::ID_SHA_1 => MessageDigest::sha1(),
+        oid if oid == rfc5912::ID_SHA_256 => MessageDigest::sha256(),
+        oid if oid == rfc5912::ID_SHA_384 => MessageDigest::sha384(),
+        oid if oid == rfc5912::ID_SHA_512 => MessageDigest::sha512(),
+        _ => return Ok(false),
+    };
+
+    let name_hash = hash(digest, &ca_cert.subject_name().to_der()?)?;
+    let key_hash = public_key_hash(ca_cert, digest)?;
+
+    Ok(cert_id.issuer_name_hash.as_bytes() == &*name_hash
+        && cert_id.issuer_key_hash.as_bytes() == key_hash.as_slice())
+}
+
+/// Whether `cert` has the id-kp-OCSPSigning extended key usage, without
+/// which clients reject it as a delegated responder (RFC 6960 section 4.2.2.2).
+fn has_ocsp_signing_usage(cert: &X509) -> Result<bool> {
+    let cert = Certificate::from_der(&cert.to_der()?)?;
+    let extensions = cert.tbs_certificate.extensions.unwrap_or_default();
+    match extensions
+        .iter()
+        .find(|ext| ext.extn_id == rfc5912::ID_CE_EXT_KEY_USAGE)
+    {
+        Some(ext) => Ok(ExtendedKeyUsage::from_der(ext.extn_value.as_bytes())?
+            .0
+            .contains(&rfc5912::ID_KP_OCSP_SIGNING)),
+        None => Ok(false),
+    }
+}
+
+fn revoked_info(record: &CertificateRecord) -> Result<RevokedInfo> {
+    let reason = record
+        .revocation_reason
+        .as_deref()
+        .map(parse_reason)
+        .filter(|reason| *reason != CrlReason::Unspecified);
+
+    Ok(RevokedInfo {
+        revocation_time: generalized_time(record.revoked_at.unwrap_or(record.issued_at))?,
+        revocation_reason: reason,
+    })
+}
+
+/// Hash of the subjectPublicKey BIT STRING contents, as used for CertID and
+/// ResponderID byKey.
+fn public_key_hash(cert: &X509, digest: MessageDigest) -> Result<Vec<u8>> {
+    let spki = SubjectPublicKeyInfoOwned::from_der(&cert.public_key()?.public_key_to_der()?)?;
+    Ok(hash(digest, spki.subject_public_key.raw_bytes())?.to_vec())
+}
+
+fn generalized_time(timestamp: i64) -> Result<GeneralizedTime> {
+    Ok(GeneralizedTime::from_unix_duration(Duration::from_secs(
+        timestamp.max(0) as u64,
+    ))?)
+}
+
+fn error_response(status: OcspResponseStatus) -> Vec<u8> {
+    OcspResponse {
+        response_status: status,
+        response_bytes: None,
+    }
+    .to_der()
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::certificate::tests::{request, test_manager, test_manager_with};
+    use crate::certificate::{CertificateRequest, IssuedCertificate};
+    use crate::config::{CertificateProfile, RotationConfig};
+    use crate::rotation::CaRotation;
+    use openssl::sign::Verifier;
+    use tempfile::TempDir;
+
+    /// CertID of `issued` under `ca`, hashed with SHA-1 as most clients do.
+    pub(crate) fn cert_id(ca: &X509, issued: &IssuedCertificate) -> CertId {
+        let cert = X509::from_pem(issued.certificate_pem.as_bytes()).unwrap();
+        let cert = Certificate::from_der(&cert.to_der().unwrap()).unwrap();
+        CertId {
+            hash_algorithm: AlgorithmIdentifierOwned {
+                oid: rfc5912::ID_SHA_1,
+                parameters: None,
+            },
+            issuer_name_hash: OctetString::new(
+                hash(MessageDigest::sha1(), &ca.subject_name().to_der().unwrap())
+                    .unwrap()
+                    .to_vec(),
+            )
+            .unwrap(),
+            issuer_key_hash: OctetString::new(public_key_hash(ca, MessageDigest::sha1()).unwrap())
+                .unwrap(),
+            serial_number: cert.tbs_certificate.serial_number,
+        }
+    }
+
+    /// DER OCSP request for `cert_ids`, carrying `nonce` when given.
+    pub(crate) fn ocsp_request(cert_ids: Vec<CertId>, nonce: Option<&[u8]>) -> Vec<u8> {
+        let request_extensions = nonce.map(|nonce| {
+            vec![Extension {
+                extn_id: rfc6960::ID_PKIX_OCSP_NONCE,
+                critical: false,
+                extn_value: OctetString::new(OctetString::new(nonce).unwrap().to_der().unwrap())
+                    .unwrap(),
+            }]
+        });
+        OcspRequest {
+            tbs_request: TbsRequest {
+                version: Version::V1,
+                requestor_name: None,
+                request_list: cert_ids
+                    .into_iter()
+                    .map(|req_cert| Request {
+                        req_cert,
+                        single_request_extensions: None,
+                    })
+                    .collect(),
+                request_extensions,
+            },
+            optional_signature: None,
+        }
+        .to_der()
+        .unwrap()
+    }
+
+    /// The basic response in a successful OCSP response, after checking its
+    /// signature against `signer`.
+    pub(crate) fn basic_response(response_der: &[u8], signer: &X509) -> BasicOcspResponse {
+        let response = OcspResponse::from_der(response_der).unwrap();
+        assert_eq!(response.response_status, OcspResponseStatus::Successful);
+        let basic =
+            BasicOcspResponse::from_der(response.response_bytes.unwrap().response.as_bytes())
+                .unwrap();
+
+        let public_key = signer.public_key().unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key).unwrap();
+        let signed = basic.tbs_response_data.to_der().unwrap();
+        assert!(verifier
+            .verify_oneshot(basic.signature.raw_bytes(), &signed)
+            .unwrap());
+        basic
+    }
+
+    async fn responder(
+        cert_manager: &CertificateManager,
+        store: Arc<dyn CertificateStore>,
+    ) -> OcspResponder {
+        OcspResponder::new(cert_manager, &RevocationConfig::default(), store)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_for_every_certificate_in_the_request() {
+        let (cert_manager, store, _dir) = test_manager().await;
+        let responder = responder(&cert_manager, store.clone()).await;
+        let good = cert_manager
+            .issue_certificate(request("good.example.com"))
+            .await
+            .unwrap();
+        let revoked = cert_manager
+            .issue_certificate(request("revoked.example.com"))
+            .await
+            .unwrap();
+        cert_manager
+            .revoke_certificate(&revoked.certificate_id, Some("keyCompromise"))
+            .await
+            .unwrap();
+        let ca = cert_manager.ca();
+
+        // A certificate from another CA is unknown
+        let (other_manager, _other_store, _other_dir) = test_manager().await;
+        let foreign = other_manager
+            .issue_certificate(request("foreign.example.com"))
+            .await
+            .unwrap();
+
+        let request_der = ocsp_request(
+            vec![
+                cert_id(&ca.cert, &good),
+                cert_id(&ca.cert, &revoked),
+                cert_id(&other_manager.ca().cert, &foreign),
+            ],
+            Some(b"0123456789abcdef"),
+        );
+        let basic = basic_response(&responder.respond(&request_der).await, &ca.cert);
+        let data = basic.tbs_response_data;
+
+        let statuses: Vec<_> = data.responses.iter().map(|r| &r.cert_status).collect();
+        assert!(matches!(statuses[0], CertStatus::Good(_)));
+        match statuses[1] {
+            CertStatus::Revoked(info) => {
+                assert_eq!(info.revocation_reason, Some(CrlReason::KeyCompromise))
+            }
+            status => panic!("expected revoked, got {:?}", status),
+        }
+        assert!(matches!(statuses[2], CertStatus::Unknown(_)));
+        assert_eq!(data.responses[0].cert_id, cert_id(&ca.cert, &good));
+
+        let nonce = &data.response_extensions.unwrap()[0];
+        assert_eq!(nonce.extn_id, rfc6960::ID_PKIX_OCSP_NONCE);
+        assert_eq!(
+            OctetString::from_der(nonce.extn_value.as_bytes()).unwrap(),
+            OctetString::new(&b"0123456789abcdef"[..]).unwrap()
+        );
+        assert!(basic.certs.is_none());
+    }
+
+    #[tokio::test]
+    async fn reports_malformed_requests() {
+        let (cert_manager, store, _dir) = test_manager().await;
+        let responder = responder(&cert_manager, store).await;
+
+        let response = OcspResponse::from_der(&responder.respond(b"not a request").await).unwrap();
+        assert_eq!(
+            response.response_status,
+            OcspResponseStatus::MalformedRequest
+        );
+        assert!(response.response_bytes.is_none());
+    }
+
+    #[tokio::test]
+    async fn answers_for_the_previous_ca_during_rotation() {
+        let (cert_manager, store, _dir) = test_manager().await;
+        let store: Arc<dyn CertificateStore> = store;
+        let responder = responder(&cert_manager, store.clone()).await;
+        let before = cert_manager
+            .issue_certificate(request("before.example.com"))
+            .await
+            .unwrap();
+        let previous_ca = cert_manager.ca();
+
+        let rotation = CaRotation::new(
+            cert_manager.clone(),
+            store.clone(),
+            RotationConfig::default(),
+        );
+        rotation.stage(None, None, None).await.unwrap();
+        rotation.activate().await.unwrap();
+        let after = cert_manager
+            .issue_certificate(request("after.example.com"))
+            .await
+            .unwrap();
+        let active_ca = cert_manager.ca();
+
+        // Each CA answers for its own certificates
+        let request_der = ocsp_request(vec![cert_id(&previous_ca.cert, &before)], None);
+        let basic = basic_response(&responder.respond(&request_der).await, &previous_ca.cert);
+        assert!(matches!(
+            basic.tbs_response_data.responses[0].cert_status,
+            CertStatus::Good(_)
+        ));
+
+        let request_der = ocsp_request(vec![cert_id(&active_ca.cert, &after)], None);
+        let basic = basic_response(&responder.respond(&request_der).await, &active_ca.cert);
+        assert!(matches!(
+            basic.tbs_response_data.responses[0].cert_status,
+            CertStatus::Good(_)
+        ));
+
+        // Once the previous CA is retired its certificates are unknown
+        rotation.retire(true).await.unwrap();
+        let request_der = ocsp_request(vec![cert_id(&previous_ca.cert, &before)], None);
+        let basic = basic_response(&responder.respond(&request_der).await, &active_ca.cert);
+        assert!(matches!(
+            basic.tbs_response_data.responses[0].cert_status,
+            CertStatus::Unknown(_)
+        ));
+    }
+
+    /// A manager with an `ocsp` profile, plus a certificate it issued under
+    /// `profile` written to `signer.crt` and `signer.key` in its directory.
+    async fn delegated_signer(
+        profile: &str,
+    ) -> (
+        CertificateManager,
+        Arc<dyn CertificateStore>,
+        TempDir,
+        RevocationConfig,
+    ) {
+        let (cert_manager, store, dir) = test_manager_with(|config| {
+            config.certificate.profiles.insert(
+                "ocsp".to_string(),
+                CertificateProfile {
+                    extended_key_usages: vec!["ocsp-signing".to_string()],
+                    ..Default::default()
+                },
+            );
+        })
+        .await;
+        let signer = cert_manager
+            .issue_certificate(CertificateRequest {
+                profile: Some(profile.to_string()),
+                ..request("ocsp.example.com")
+            })
+            .await
+            .unwrap();
+
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        fs::write(path("signer.crt"), &signer.certificate_pem)
+            .await
+            .unwrap();
+        fs::write(path("signer.key"), signer.private_key_pem.unwrap())
+            .await
+            .unwrap();
+        let config = RevocationConfig {
+            ocsp_signer_cert_path: Some(path("signer.crt")),
+            ocsp_signer_key_path: Some(path("signer.key")),
+            ..Default::default()
+        };
+        (cert_manager, store, dir, config)
+    }
+
+    #[tokio::test]
+    async fn signs_with_a_delegated_responder() {
+        let (cert_manager, store, _dir, config) = delegated_signer("ocsp").await;
+        let responder = OcspResponder::new(&cert_manager, &config, store)
+            .await
+            .unwrap();
+        let issued = cert_manager
+            .issue_certificate(request("api.example.com"))
+            .await
+            .unwrap();
+        let signer_cert = X509::from_pem(
+            &fs::read(config.ocsp_signer_cert_path.unwrap())
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+
+        let request_der = ocsp_request(vec![cert_id(&cert_manager.ca().cert, &issued)], None);
+        let basic = basic_response(&responder.respond(&request_der).await, &signer_cert);
+        assert_eq!(
+            basic.certs.unwrap()[0].to_der().unwrap(),
+            signer_cert.to_der().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_delegated_responders_without_ocsp_signing_usage() {
+        let (cert_manager, store, _dir, config) = delegated_signer("server").await;
+
+        let error = OcspResponder::new(&cert_manager, &config, store)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("OCSPSigning"));
+    }
+}
@@ -0,0 +1,329 @@
+use crate::certificate::CertificateRequest;
+use crate::config::PolicyConfig;
+use crate::error::{CertAgentError, Result};
+use std::net::IpAddr;
+use tracing::warn;
+
+/// Issuance policy checked before any certificate is signed, whatever the
+/// path (IssueCertificate, SignCsr or renewal). Empty lists allow everything.
+#[derive(Debug, Clone)]
+pub struct IssuancePolicy {
+    config: PolicyConfig,
+}
+
+impl IssuancePolicy {
+    pub fn new(config: &PolicyConfig) -> Self {
+        let normalize = |suffixes: &[String]| {
+            suffixes
+                .iter()
+                .map(|suffix| suffix.trim_start_matches("*.").trim_start_matches('.'))
+                .map(|suffix| suffix.to_ascii_lowercase())
+                .collect()
+        };
+
+        Self {
+            config: PolicyConfig {
+                allowed_domains: normalize(&config.allowed_domains),
+                denied_domains: normalize(&config.denied_domains),
+                ..config.clone()
+            },
+        }
+    }
+
+    /// Checks `request` once its validity and subject are final. Every
+    /// denial is logged with the reason returned to the caller.
+    pub fn evaluate(&self, request: &CertificateRequest) -> Result<()> {
+        self.check(request).map_err(|reason| {
+            warn!(
+                "Issuance policy denied certificate for CN {}: {}",
+                request.common_name, reason
+            );
+            CertAgentError::PolicyViolation(reason)
+        })
+    }
+
+    fn check(&self, request: &CertificateRequest) -> std::result::Result<(), String> {
+        if let Some(max) = self.config.max_validity_days {
+            if request.validity_days > max {
+                return Err(format!(
+                    "validity of {} days exceeds the maximum of {} days",
+                    request.validity_days, max
+                ));
+            }
+        }
+
+        // A common name that is an address or a host name, including a
+        // single-label one such as `localhost`, is matched by TLS clients,
+        // so it is held to the same rules as the SANs
+        match request.common_name.parse::<IpAddr>() {
+            Ok(ip) => self.check_ip(ip)?,
+            Err(_) if is_host_name(&request.common_name) => {
+                self.check_dns_name(&request.common_name)?
+            }
+            Err(_) => {}
+        }
+        for dns_name in &request.dns_names {
+            self.check_dns_name(dns_name)?;
+        }
+        for ip_address in &request.ip_addresses {
+            let ip = ip_address
+                .parse::<IpAddr>()
+                .map_err(|_| format!("invalid IP address {}", ip_address))?;
+            self.check_ip(ip)?;
+        }
+
+        for key in &self.config.required_metadata {
+            if request
+                .metadata
+                .get(key)
+                .is_none_or(|value| value.is_empty())
+            {
+                return Err(format!("metadata key {} is required", key));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_dns_name(&self, name: &str) -> std::result::Result<(), String> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+
+        // A wildcard is judged by the domain it covers
+        let domain = match name.strip_prefix("*.") {
+            Some(domain) => {
+                if !self.config.allow_wildcards {
+                    return Err(format!("wildcard name {} is not allowed", name));
+                }
+                // It must not cover a name the policy denies
+                if let Some(denied) = self
+                    .config
+                    .denied_domains
+                    .iter()
+                    .find(|denied| is_subdomain(denied, domain) && denied.as_str() != domain)
+                {
+                    return Err(format!(
+                        "wildcard name {} would cover denied domain {}",
+                        name, denied
+                    ));
+                }
+                domain
+            }
+            None => name.as_str(),
+        };
+
+        if let Some(denied) = self
+            .config
+            .denied_domains
+            .iter()
+            .find(|denied| is_subdomain(domain, denied))
+        {
+            return Err(format!(
+                "DNS name {} is under denied domain {}",
+                name, denied
+            ));
+        }
+
+        if !self.config.allowed_domains.is_empty()
+            && !self
+                .config
+                .allowed_domains
+                .iter()
+                .any(|allowed| is_subdomain(domain, allowed))
+        {
+            return Err(format!(
+                "DNS name {} is not under an allowed domain ({})",
+                name,
+                self.config.allowed_domains.join(", ")
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_ip(&self, ip: IpAddr) -> std::result::Result<(), String> {
+        if !self.config.allowed_ip_ranges.is_empty()
+            && !self
+                .config
+                .allowed_ip_ranges
+                .iter()
+                .any(|range| range.contains(&ip))
+        {
+            return Err(format!("IP address {} is not in an allowed range", ip));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `name` equals `domain` or lies below it, on label boundaries.
+fn is_subdomain(name: &str, domain: &str) -> bool {
+    name == domain
+        || name
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// Whether `name` is a syntactically valid host name (RFC 1123 labels,
+/// optionally under a leading wildcard and with a trailing dot).
+fn is_host_name(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let name = name.strip_prefix("*.").unwrap_or(name);
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::tests::request;
+
+    fn policy(configure: impl FnOnce(&mut PolicyConfig)) -> IssuancePolicy {
+        let mut config = PolicyConfig::default();
+        configure(&mut config);
+        IssuancePolicy::new(&config)
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    /// A request naming only `dns_names` and `ip_addresses`, under a common
+    /// name that is not host-like.
+    fn sans(dns_names: &[&str], ip_addresses: &[&str]) -> CertificateRequest {
+        CertificateRequest {
+            common_name: "Payments Service".to_string(),
+            dns_names: strings(dns_names),
+            ip_addresses: strings(ip_addresses),
+            ..request("api.example.com")
+        }
+    }
+
+    #[test]
+    fn denies_names_under_denied_domains() {
+        let policy = policy(|config| config.denied_domains = strings(&["*.Corp.Example.com"]));
+
+        assert!(policy.evaluate(&sans(&["api.example.com"], &[])).is_ok());
+        for name in [
+            "corp.example.com",
+            "db.corp.example.com",
+            "DB.corp.example.com.",
+        ] {
+            assert!(matches!(
+                policy.evaluate(&sans(&[name], &[])),
+                Err(CertAgentError::PolicyViolation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn matches_domains_on_label_boundaries() {
+        let allowed = policy(|config| config.allowed_domains = strings(&["example.com"]));
+        assert!(allowed.evaluate(&sans(&["example.com"], &[])).is_ok());
+        assert!(allowed.evaluate(&sans(&["api.example.com"], &[])).is_ok());
+        assert!(allowed.evaluate(&sans(&["badexample.com"], &[])).is_err());
+        assert!(allowed
+            .evaluate(&sans(&["example.com.evil.org"], &[]))
+            .is_err());
+
+        let denied = policy(|config| config.denied_domains = strings(&["example.com"]));
+        assert!(denied.evaluate(&sans(&["badexample.com"], &[])).is_ok());
+        assert!(denied.evaluate(&sans(&["api.example.com"], &[])).is_err());
+    }
+
+    #[test]
+    fn refuses_wildcards_covering_denied_domains() {
+        let denied = policy(|config| config.denied_domains = strings(&["secret.example.com"]));
+
+        assert!(denied.evaluate(&sans(&["*.api.example.com"], &[])).is_ok());
+        assert!(denied.evaluate(&sans(&["*.example.com"], &[])).is_err());
+        assert!(denied
+            .evaluate(&sans(&["*.secret.example.com"], &[]))
+            .is_err());
+        assert!(denied
+            .evaluate(&sans(&["*.badsecret.example.com"], &[]))
+            .is_ok());
+
+        let no_wildcards = policy(|config| config.allow_wildcards = false);
+        assert!(no_wildcards
+            .evaluate(&sans(&["*.api.example.com"], &[]))
+            .is_err());
+        assert!(no_wildcards
+            .evaluate(&sans(&["www.api.example.com"], &[]))
+            .is_ok());
+    }
+
+    #[test]
+    fn keeps_ip_addresses_within_allowed_ranges() {
+        let policy = policy(|config| {
+            config.allowed_ip_ranges =
+                vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()]
+        });
+
+        assert!(policy.evaluate(&sans(&[], &["10.1.2.3"])).is_ok());
+        assert!(policy.evaluate(&sans(&[], &["fd12::1"])).is_ok());
+        assert!(policy.evaluate(&sans(&[], &["11.0.0.1"])).is_err());
+        assert!(policy.evaluate(&sans(&[], &["2001:db8::1"])).is_err());
+        assert!(policy.evaluate(&sans(&[], &["not-an-address"])).is_err());
+    }
+
+    #[test]
+    fn caps_validity() {
+        let policy = policy(|config| config.max_validity_days = Some(90));
+
+        let within = CertificateRequest {
+            validity_days: 90,
+            ..sans(&[], &[])
+        };
+        let beyond = CertificateRequest {
+            validity_days: 91,
+            ..sans(&[], &[])
+        };
+        assert!(policy.evaluate(&within).is_ok());
+        assert!(policy.evaluate(&beyond).is_err());
+    }
+
+    #[test]
+    fn requires_non_empty_metadata() {
+        let policy = policy(|config| config.required_metadata = strings(&["team", "cost-center"]));
+
+        let mut request = sans(&[], &[]);
+        assert!(policy.evaluate(&request).is_err());
+        request
+            .metadata
+            .insert("cost-center".to_string(), String::new());
+        assert!(policy.evaluate(&request).is_err());
+        request
+            .metadata
+            .insert("cost-center".to_string(), "cc-42".to_string());
+        assert!(policy.evaluate(&request).is_ok());
+    }
+
+    #[test]
+    fn holds_host_like_common_names_to_the_san_rules() {
+        let policy = policy(|config| {
+            config.allowed_domains = strings(&["example.com"]);
+            config.allowed_ip_ranges = vec!["10.0.0.0/8".parse().unwrap()];
+        });
+        let named = |common_name: &str| CertificateRequest {
+            common_name: common_name.to_string(),
+            ..sans(&[], &[])
+        };
+
+        assert!(policy.evaluate(&named("api.example.com")).is_ok());
+        assert!(policy.evaluate(&named("api.other.org")).is_err());
+        assert!(policy.evaluate(&named("10.0.0.1")).is_ok());
+        assert!(policy.evaluate(&named("192.168.0.1")).is_err());
+        // Single-label host names are host names too
+        assert!(policy.evaluate(&named("localhost")).is_err());
+        assert!(policy.evaluate(&named("intranet")).is_err());
+        // Not a host name, so not matched against the domains
+        assert!(policy.evaluate(&named("Payments Service")).is_ok());
+        assert!(policy.evaluate(&named("payments_service")).is_ok());
+    }
+}
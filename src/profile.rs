@@ -0,0 +1,268 @@
+use crate::certificate::{der_extension, CertificateRequest};
+use crate::config::{CertificateConfig, CertificateProfile, KeyAlgorithm};
+use crate::error::{CertAgentError, Result};
+use base64::{engine::general_purpose, Engine as _};
+use openssl::x509::{
+    extension::{ExtendedKeyUsage, KeyUsage},
+    X509Builder, X509Extension,
+};
+use std::collections::HashMap;
+use x509_cert::der::oid::ObjectIdentifier;
+
+/// Profile used when a request does not name one.
+pub const DEFAULT_PROFILE: &str = "default";
+
+const SUBJECT_FIELDS: [&str; 5] = [
+    "organization",
+    "organizational_unit",
+    "country",
+    "state",
+    "locality",
+];
+
+/// Profiles available without configuration. Entries in
+/// `CertificateConfig::profiles` with the same name replace them.
+pub fn builtin_profiles() -> HashMap<String, CertificateProfile> {
+    let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+    HashMap::from([
+        (
+            DEFAULT_PROFILE.to_string(),
+            CertificateProfile {
+                extended_key_usages: names(&["server-auth", "client-auth"]),
+                ..Default::default()
+            },
+        ),
+        (
+            "server".to_string(),
+            CertificateProfile {
+                extended_key_usages: names(&["server-auth"]),
+                ..Default::default()
+            },
+        ),
+        (
+            "client".to_string(),
+            CertificateProfile {
+                extended_key_usages: names(&["client-auth"]),
+                ..Default::default()
+            },
+        ),
+        (
+            "code-signing".to_string(),
+            CertificateProfile {
+                key_usages: names(&["digital-signature"]),
+                extended_key_usages: names(&["code-signing"]),
+                max_validity_days: Some(1095),
+                required_subject_fields: names(&["organization"]),
+                ..Default::default()
+            },
+        ),
+        (
+            "workload".to_string(),
+            CertificateProfile {
+                extended_key_usages: names(&["server-auth", "client-auth"]),
+                max_validity_days: Some(1),
+                ..Default::default()
+            },
+        ),
+    ])
+}
+
+/// Looks up `name` (or the default profile) in the configured and built-in
+/// profiles.
+pub fn resolve(
+    config: &CertificateConfig,
+    name: Option<&str>,
+) -> Result<(String, CertificateProfile)> {
+    let name = name.unwrap_or(DEFAULT_PROFILE);
+    let profile = match config.profiles.get(name) {
+        Some(profile) => profile.clone(),
+        None => builtin_profiles().remove(name).ok_or_else(|| {
+            CertAgentError::InvalidRequest(format!("Unknown certificate profile: {}", name))
+        })?,
+    };
+
+    Ok((name.to_string(), profile))
+}
+
+/// Checks every configured profile at startup so typos fail fast instead of
+/// on the first request that uses the profile.
+pub fn validate_profiles(config: &CertificateConfig) -> Result<()> {
+    for (name, profile) in &config.profiles {
+        let invalid = |e: CertAgentError| {
+            CertAgentError::Config(config::ConfigError::Message(format!(
+                "Invalid certificate profile {}: {}",
+                name, e
+            )))
+        };
+
+        if let Some(field) = profile
+            .required_subject_fields
+            .iter()
+            .find(|field| !SUBJECT_FIELDS.contains(&field.as_str()))
+        {
+            return Err(invalid(CertAgentError::InvalidRequest(format!(
+                "Unknown subject field: {}",
+                field
+            ))));
+        }
+
+        extensions(profile, config.key_algorithm).map_err(invalid)?;
+    }
+
+    Ok(())
+}
+
+/// Applies the profile's validity default and limits to `request`, which
+/// must already carry its final key type and subject.
+pub fn apply_to_request(
+    name: &str,
+    profile: &CertificateProfile,
+    request: &mut CertificateRequest,
+    key_algorithm: KeyAlgorithm,
+    default_validity_days: u32,
+) -> Result<()> {
+    if request.validity_days == 0 {
+        request.validity_days = match profile.max_validity_days {
+            Some(max) => default_validity_days.min(max),
+            None => default_validity_days,
+        };
+    }
+
+    if let Some(max) = profile.max_validity_days {
+        if request.validity_days > max {
+            return Err(CertAgentError::InvalidRequest(format!(
+                "Profile {} allows at most {} days of validity, {} requested",
+                name, max, request.validity_days
+            )));
+        }
+    }
+
+    if !profile.allowed_key_algorithms.is_empty()
+        && !profile.allowed_key_algorithms.contains(&key_algorithm)
+    {
+        return Err(CertAgentError::InvalidRequest(format!(
+            "Profile {} does not allow {} keys",
+            name, key_algorithm
+        )));
+    }
+
+    for field in &profile.required_subject_fields {
+        let value = match field.as_str() {
+            "organization" => &request.organization,
+            "organizational_unit" => &request.organizational_unit,
+            "country" => &request.country,
+            "state" => &request.state,
+            "locality" => &request.locality,
+            _ => &None,
+        };
+        if value.as_deref().unwrap_or_default().is_empty() {
+            return Err(CertAgentError::InvalidRequest(format!(
+                "Profile {} requires subject field {}",
+                name, field
+            )));
+        }
+    }
+
+    request.profile = Some(name.to_string());
+    Ok(())
+}
+
+/// Appends the profile's key usage, extended key usage and extra extensions.
+pub fn append_extensions(
+    cert_builder: &mut X509Builder,
+    profile: &CertificateProfile,
+    key_algorithm: KeyAlgorithm,
+) -> Result<()> {
+    for extension in extensions(profile, key_algorithm)? {
+        cert_builder.append_extension(extension)?;
+    }
+    Ok(())
+}
+
+fn extensions(
+    profile: &CertificateProfile,
+    key_algorithm: KeyAlgorithm,
+) -> Result<Vec<X509Extension>> {
+    let mut extensions = vec![key_usage(profile, key_algorithm)?];
+
+    if !profile.extended_key_usages.is_empty() {
+        let mut eku = ExtendedKeyUsage::new();
+        for name in &profile.extended_key_usages {
+            match name.as_str() {
+                "server-auth" => eku.server_auth(),
+                "client-auth" => eku.client_auth(),
+                "code-signing" => eku.code_signing(),
+                "email-protection" => eku.email_protection(),
+                "time-stamping" => eku.time_stamping(),
+                "ocsp-signing" => eku.other("OCSPSigning"),
+                oid => {
+                    parse_oid(oid)?;
+                    eku.other(oid)
+                }
+            };
+        }
+        extensions.push(eku.build()?);
+    }
+
+    for extension in &profile.extensions {
+        let value = general_purpose::STANDARD
+            .decode(&extension.value)
+            .map_err(|e| {
+                CertAgentError::InvalidRequest(format!(
+                    "Extension {} value is not valid base64: {}",
+                    extension.oid, e
+                ))
+            })?;
+        extensions.push(der_extension(
+            parse_oid(&extension.oid)?,
+            extension.critical,
+            &value,
+        )?);
+    }
+
+    Ok(extensions)
+}
+
+// Key encipherment only applies to RSA keys; EC keys use key agreement for
+// key exchange instead.
+fn key_usage(profile: &CertificateProfile, key_algorithm: KeyAlgorithm) -> Result<X509Extension> {
+    let mut key_usage = KeyUsage::new();
+
+    if profile.key_usages.is_empty() {
+        key_usage.digital_signature();
+        match key_algorithm {
+            KeyAlgorithm::Rsa => {
+                key_usage.key_encipherment();
+            }
+            KeyAlgorithm::EcdsaP256 | KeyAlgorithm::EcdsaP384 => {
+                key_usage.key_agreement();
+            }
+            KeyAlgorithm::Ed25519 => {}
+        }
+        return Ok(key_usage.build()?);
+    }
+
+    for name in &profile.key_usages {
+        match name.as_str() {
+            "digital-signature" => key_usage.digital_signature(),
+            "non-repudiation" | "content-commitment" => key_usage.non_repudiation(),
+            "key-encipherment" => key_usage.key_encipherment(),
+            "data-encipherment" => key_usage.data_encipherment(),
+            "key-agreement" => key_usage.key_agreement(),
+            other => {
+                return Err(CertAgentError::InvalidRequest(format!(
+                    "Unknown key usage: {}",
+                    other
+                )))
+            }
+        };
+    }
+
+    Ok(key_usage.build()?)
+}
+
+fn parse_oid(oid: &str) -> Result<ObjectIdentifier> {
+    ObjectIdentifier::new(oid)
+        .map_err(|_| CertAgentError::InvalidRequest(format!("Invalid OID: {}", oid)))
+}
@@ -1,28 +1,59 @@
 use crate::error::{CertAgentError, Result};
+use crate::store::{
+    canonical_ip, decode_page_token, encode_page_token, issuance_events, mark_revoked,
+    mark_superseded, CaRotationRecord, CertificatePage, CertificateQuery, CertificateRecord,
+    CertificateStore, EventStream, IdempotencyRecord, ListSort, StoreRepair, LIST_BATCH_SIZE,
+};
+use async_trait::async_trait;
+use futures::StreamExt;
 use redis::aio::ConnectionManager;
 use redis::{AsyncCommands, Client};
-use serde::{Deserialize, Serialize};
-// use std::time::Duration; // Not used currently
+use tracing::info;
+use uuid::Uuid;
+
+/// Listing index over all certificates. Every member has score 0 and is
+/// `{issued_at:020}:{certificate_id}`, so a lexicographic range walks the
+/// certificates in issue order and a member is a stable pagination cursor.
+const CERT_INDEX: &str = "certs:index";
+
+/// Every certificate scored by `expires_at`, for sorting and expiry ranges.
+const EXPIRY_INDEX: &str = "certs:by_expiry";
+
+/// Active certificates only, scored by `expires_at`, for the watcher's
+/// expiry checks.
+const ACTIVE_EXPIRY_INDEX: &str = "certs:active_by_expiry";
+
+/// Common name prefix search: members are `{lowercase common name}\0{index member}`.
+const CN_PREFIX_INDEX: &str = "certs:cn_prefix";
+
+/// Pub/sub channel certificate events are published on.
+const EVENTS_CHANNEL: &str = "cert_events";
+
+/// Bumped whenever a new index is added or record TTLs change, so
+/// `ensure_indexes` backfills them.
+const INDEX_VERSION: u32 = 5;
+const INDEX_VERSION_KEY: &str = "certs:index:version";
+
+/// Intersections built for a query are deleted afterwards; the TTL only
+/// covers a crash in between.
+const QUERY_KEY_TTL_SECONDS: i64 = 60;
+
+/// Records outlive their retention window by this much, so the watcher's
+/// cleanup, which also drops their index entries, normally deletes them
+/// before Redis does.
+const RECORD_TTL_GRACE_SECONDS: i64 = 24 * 60 * 60;
 
 #[derive(Debug, Clone)]
 pub struct RedisClient {
     client: Client,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CertificateRecord {
-    pub certificate_id: String,
-    pub common_name: String,
-    pub dns_names: Vec<String>,
-    pub ip_addresses: Vec<String>,
-    pub status: String,
-    pub expires_at: i64,
-    pub issued_at: i64,
-    pub metadata: std::collections::HashMap<String, String>,
+    /// How long records are kept past `expires_at`; `None` keeps them forever
+    retention_seconds: Option<i64>,
 }
 
 impl RedisClient {
-    pub async fn new(url: &str) -> Result<Self> {
+    /// Connects to Redis. Records expire `retention_days` after the
+    /// certificate does; 0 keeps them forever.
+    pub async fn new(url: &str, retention_days: u32) -> Result<Self> {
         let client = Client::open(url).map_err(CertAgentError::Redis)?;
 
         // Test connection
@@ -36,7 +67,11 @@ impl RedisClient {
             .await
             .map_err(CertAgentError::Redis)?;
 
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            retention_seconds: Some(retention_days as i64 * 24 * 60 * 60)
+                .filter(|seconds| *seconds > 0),
+        })
     }
 
     pub async fn get_connection(&self) -> Result<ConnectionManager> {
@@ -46,26 +81,244 @@ impl RedisClient {
             .map_err(CertAgentError::Redis)
     }
 
-    // Certificate operations
-    pub async fn store_certificate(&self, cert_record: &CertificateRecord) -> Result<()> {
+    /// Seconds `cert_record` is kept from now: until the retention window
+    /// after its expiry has passed, or forever without retention.
+    fn record_ttl(&self, cert_record: &CertificateRecord) -> Option<u64> {
+        self.retention_seconds.map(|retention| {
+            let expires = cert_record.expires_at + retention + RECORD_TTL_GRACE_SECONDS;
+            (expires - chrono::Utc::now().timestamp()).max(1) as u64
+        })
+    }
+
+    /// Writes `value` under a key belonging to `cert_record`, with the
+    /// record's TTL. The TTL is derived again on every write, so status
+    /// updates neither drop nor extend it.
+    fn set_record(
+        &self,
+        pipe: &mut redis::Pipeline,
+        key: &str,
+        value: String,
+        cert_record: &CertificateRecord,
+    ) {
+        match self.record_ttl(cert_record) {
+            Some(ttl) => pipe.set_ex(key, value, ttl).ignore(),
+            None => pipe.set(key, value).ignore(),
+        };
+    }
+
+    /// Brings the listing and search indexes and record TTLs up to
+    /// `INDEX_VERSION`, rebuilding them from the records for data written by
+    /// older versions.
+    pub async fn ensure_indexes(&self) -> Result<()> {
         let mut conn = self.get_connection().await?;
-        let key = format!("cert:{}", cert_record.certificate_id);
-        let value = serde_json::to_string(cert_record)?;
+        let version: Option<u32> = conn
+            .get(INDEX_VERSION_KEY)
+            .await
+            .map_err(CertAgentError::Redis)?;
+        if version.is_some_and(|version| version >= INDEX_VERSION) {
+            return Ok(());
+        }
 
-        conn.set_ex::<_, _, ()>(&key, value, 365 * 24 * 60 * 60)
+        // Walk the records themselves rather than certs:all, which older
+        // versions did not keep complete
+        let mut keys: Vec<String> = Vec::new();
+        let mut iter: redis::AsyncIter<String> = conn
+            .scan_match("cert:*")
             .await
             .map_err(CertAgentError::Redis)?;
+        while let Some(key) = iter.next_item().await {
+            // Skip cert:serial:* and other auxiliary keys
+            if !key["cert:".len()..].contains(':') {
+                keys.push(key);
+            }
+        }
+        drop(iter);
+
+        if !keys.is_empty() {
+            info!(
+                "Building certificate indexes for {} certificates",
+                keys.len()
+            );
+        }
+        for batch in keys.chunks(LIST_BATCH_SIZE) {
+            let values: Vec<Option<String>> = redis::cmd("MGET")
+                .arg(batch)
+                .query_async(&mut conn)
+                .await
+                .map_err(CertAgentError::Redis)?;
+
+            let mut pipe = redis::pipe();
+            for (key, cert_record) in batch.iter().zip(values) {
+                let Some(cert_record) = cert_record else {
+                    continue;
+                };
+                let cert_record: CertificateRecord = serde_json::from_str(&cert_record)?;
+                pipe.sadd("certs:all", &cert_record.certificate_id).ignore();
+                index_record(&mut pipe, &cert_record, None);
 
+                // Older versions stored records for a fixed year, or forever
+                // once their status changed
+                let mut keys = vec![key.clone()];
+                if !cert_record.serial_number.is_empty() {
+                    keys.push(format!("cert:serial:{}", cert_record.serial_number));
+                }
+                for key in keys {
+                    match self.record_ttl(&cert_record) {
+                        Some(ttl) => pipe.expire(key, ttl as i64).ignore(),
+                        None => pipe.persist(key).ignore(),
+                    };
+                }
+            }
+            pipe.query_async::<()>(&mut conn)
+                .await
+                .map_err(CertAgentError::Redis)?;
+        }
+
+        conn.set::<_, _, ()>(INDEX_VERSION_KEY, INDEX_VERSION)
+            .await
+            .map_err(CertAgentError::Redis)
+    }
+
+    /// Queues the writes storing `cert_record` with its indexes, moving it
+    /// out of the index of `previous_status`.
+    fn write_record(
+        &self,
+        pipe: &mut redis::Pipeline,
+        cert_record: &CertificateRecord,
+        previous_status: Option<&str>,
+    ) -> Result<()> {
+        let key = format!("cert:{}", cert_record.certificate_id);
+        self.set_record(pipe, &key, serde_json::to_string(cert_record)?, cert_record);
         // Add to index for listing
-        let _: () = conn
-            .sadd("certs:all", &cert_record.certificate_id)
+        pipe.sadd("certs:all", &cert_record.certificate_id).ignore();
+        index_record(pipe, cert_record, previous_status);
+        // Index by serial number for OCSP, CRL and GetCertificateBySerial lookups
+        if !cert_record.serial_number.is_empty() {
+            self.set_record(
+                pipe,
+                &format!("cert:serial:{}", cert_record.serial_number),
+                cert_record.certificate_id.clone(),
+                cert_record,
+            );
+        }
+        Ok(())
+    }
+
+    /// Applies `change` to a certificate while the record is watched, so the
+    /// write is dropped if another writer changed it first. `change` returns
+    /// `false` to leave the record alone. The commands already in `pipe` run
+    /// in the same transaction. Returns whether it was written.
+    async fn update_watched(
+        &self,
+        certificate_id: &str,
+        change: impl FnOnce(&mut CertificateRecord) -> bool + Send,
+        mut pipe: redis::Pipeline,
+    ) -> Result<bool> {
+        // A connection of its own, since WATCH applies to the whole connection
+        let mut conn = self.get_connection().await?;
+        let key = format!("cert:{}", certificate_id);
+
+        redis::cmd("WATCH")
+            .arg(&key)
+            .query_async::<()>(&mut conn)
             .await
             .map_err(CertAgentError::Redis)?;
 
-        Ok(())
+        let value: Option<String> = conn.get(&key).await.map_err(CertAgentError::Redis)?;
+        let mut cert_record = value
+            .map(|v| serde_json::from_str::<CertificateRecord>(&v))
+            .transpose()?;
+        let previous_status = cert_record
+            .as_ref()
+            .map(|cert_record| cert_record.status.clone());
+        let changed = cert_record.as_mut().is_some_and(change);
+        let Some(cert_record) = cert_record.filter(|_| changed) else {
+            redis::cmd("UNWATCH")
+                .query_async::<()>(&mut conn)
+                .await
+                .map_err(CertAgentError::Redis)?;
+            return Ok(false);
+        };
+        let updated_value = serde_json::to_string(&cert_record)?;
+
+        pipe.atomic();
+        self.set_record(&mut pipe, &key, updated_value, &cert_record);
+        index_record(&mut pipe, &cert_record, previous_status.as_deref());
+        // EXEC returns nil when the watched record changed in the meantime
+        let committed: Option<()> = pipe
+            .query_async(&mut conn)
+            .await
+            .map_err(CertAgentError::Redis)?;
+
+        Ok(committed.is_some())
     }
 
-    pub async fn get_certificate(&self, certificate_id: &str) -> Result<Option<CertificateRecord>> {
+    /// Active certificates whose `expires_at` lies between the ZRANGEBYSCORE
+    /// bounds `min` and `max`, soonest first.
+    async fn active_expiring_between(
+        &self,
+        min: String,
+        max: String,
+    ) -> Result<Vec<CertificateRecord>> {
+        let mut conn = self.get_connection().await?;
+
+        let members: Vec<String> = conn
+            .zrangebyscore(ACTIVE_EXPIRY_INDEX, min, max)
+            .await
+            .map_err(CertAgentError::Redis)?;
+
+        let mut certs = Vec::with_capacity(members.len());
+        for batch in members.chunks(LIST_BATCH_SIZE) {
+            let batch: Vec<&String> = batch.iter().collect();
+            let records = load_members(&mut conn, &batch, ACTIVE_EXPIRY_INDEX).await?;
+            certs.extend(records.into_iter().filter(|cert| cert.status == "active"));
+        }
+
+        Ok(certs)
+    }
+}
+
+#[async_trait]
+impl CertificateStore for RedisClient {
+    // Certificate operations
+    /// The record, its indexes and its events go out in one MULTI/EXEC; a
+    /// renewal watches the predecessor, so a concurrent revocation or
+    /// renewal aborts the transaction.
+    async fn record_issuance(&self, cert_record: &CertificateRecord) -> Result<bool> {
+        let mut pipe = redis::pipe();
+        self.write_record(&mut pipe, cert_record, None)?;
+        for (event, data) in issuance_events(cert_record) {
+            pipe.publish(EVENTS_CHANNEL, event_message(event, data))
+                .ignore();
+        }
+
+        match &cert_record.predecessor_id {
+            Some(predecessor_id) => {
+                self.update_watched(
+                    predecessor_id,
+                    |predecessor| {
+                        mark_superseded(
+                            predecessor,
+                            &cert_record.certificate_id,
+                            cert_record.issued_at,
+                        )
+                    },
+                    pipe,
+                )
+                .await
+            }
+            None => {
+                let mut conn = self.get_connection().await?;
+                pipe.atomic();
+                pipe.query_async::<()>(&mut conn)
+                    .await
+                    .map_err(CertAgentError::Redis)?;
+                Ok(true)
+            }
+        }
+    }
+
+    async fn get_certificate(&self, certificate_id: &str) -> Result<Option<CertificateRecord>> {
         let mut conn = self.get_connection().await?;
         let key = format!("cert:{}", certificate_id);
 
@@ -80,86 +333,374 @@ impl RedisClient {
         }
     }
 
-    pub async fn update_certificate_status(
+    async fn get_certificate_by_serial(
+        &self,
+        serial_number: &str,
+    ) -> Result<Option<CertificateRecord>> {
+        let mut conn = self.get_connection().await?;
+        let certificate_id: Option<String> = conn
+            .get(format!("cert:serial:{}", serial_number))
+            .await
+            .map_err(CertAgentError::Redis)?;
+
+        match certificate_id {
+            Some(id) => self.get_certificate(&id).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Marks a certificate revoked, recording when and why for CRL generation.
+    /// Returns `false` if the certificate does not exist.
+    async fn record_revocation(
         &self,
         certificate_id: &str,
-        status: &str,
-    ) -> Result<()> {
+        revoked_at: i64,
+        reason: Option<&str>,
+    ) -> Result<Option<bool>> {
+        loop {
+            let Some(cert_record) = self.get_certificate(certificate_id).await? else {
+                return Ok(None);
+            };
+            if cert_record.status == "revoked" {
+                return Ok(Some(false));
+            }
+
+            let revoked = self
+                .update_watched(
+                    certificate_id,
+                    |cert_record| mark_revoked(cert_record, revoked_at, reason),
+                    redis::pipe(),
+                )
+                .await?;
+            if revoked {
+                return Ok(Some(true));
+            }
+            // Changed in the meantime: look at it again
+        }
+    }
+
+    /// Marks an active certificate superseded by its renewal `successor_id`.
+    /// Returns `false` if the certificate does not exist or was revoked,
+    /// renewed or expired in the meantime.
+    async fn supersede(
+        &self,
+        certificate_id: &str,
+        successor_id: &str,
+        superseded_at: i64,
+    ) -> Result<bool> {
+        self.update_watched(
+            certificate_id,
+            |cert_record| mark_superseded(cert_record, successor_id, superseded_at),
+            redis::pipe(),
+        )
+        .await
+    }
+
+    /// Up to `page_size` certificates matching `query` in its sort order,
+    /// starting after the cursor `page_token` returned with the previous page.
+    ///
+    /// Equality filters select candidates through their secondary indexes
+    /// (intersected when several are set) and the time range on the sort
+    /// field bounds the range scanned; the remaining filters are checked
+    /// on the fetched records.
+    async fn list_certificates_page(
+        &self,
+        query: &CertificateQuery,
+        page_size: usize,
+        page_token: Option<&str>,
+    ) -> Result<CertificatePage> {
         let mut conn = self.get_connection().await?;
-        let key = format!("cert:{}", certificate_id);
+        let mut cursor = page_token
+            .map(|token| decode_page_token(token, query.sort))
+            .transpose()?;
 
-        // Get current record
-        let value: Option<String> = conn.get(&key).await.map_err(CertAgentError::Redis)?;
+        let mut filter_keys = query_index_keys(query);
+        let mut temp_keys = Vec::new();
 
-        if let Some(v) = value {
-            let mut cert_record: CertificateRecord = serde_json::from_str(&v)?;
-            cert_record.status = status.to_string();
-            let updated_value = serde_json::to_string(&cert_record)?;
+        // Prefix matches are collected into a temporary set so they can be
+        // intersected like any other filter
+        if let Some(prefix) = &query.common_name_prefix {
+            let prefix = prefix.to_lowercase();
+            let mut max = format!("[{}", prefix).into_bytes();
+            max.push(0xff);
+            let entries: Vec<String> = conn
+                .zrangebylex(CN_PREFIX_INDEX, format!("[{}", prefix), max)
+                .await
+                .map_err(CertAgentError::Redis)?;
 
-            conn.set::<_, _, ()>(&key, updated_value)
+            let temp_key = temp_query_key();
+            let mut pipe = redis::pipe();
+            for entry in &entries {
+                if let Some((_, member)) = entry.split_once('\0') {
+                    pipe.zadd(&temp_key, member, 0).ignore();
+                }
+            }
+            pipe.expire(&temp_key, QUERY_KEY_TTL_SECONDS).ignore();
+            pipe.query_async::<()>(&mut conn)
                 .await
                 .map_err(CertAgentError::Redis)?;
+
+            filter_keys.push(temp_key.clone());
+            temp_keys.push(temp_key);
         }
 
-        Ok(())
+        let by_expiry = matches!(query.sort, ListSort::ExpiresAsc | ListSort::ExpiresDesc);
+        let driving_key = match (filter_keys.len(), by_expiry) {
+            (0, false) => CERT_INDEX.to_string(),
+            (0, true) => EXPIRY_INDEX.to_string(),
+            (1, false) => filter_keys[0].clone(),
+            _ => {
+                // Weight 0 keeps the filter sets' zero scores, so the result
+                // sorts by member (issue order); the expiry index contributes
+                // its expiry scores when sorting by expiry
+                let temp_key = temp_query_key();
+                let mut intersect = redis::cmd("ZINTERSTORE");
+                intersect.arg(&temp_key);
+                intersect.arg(filter_keys.len() + by_expiry as usize);
+                intersect.arg(&filter_keys);
+                if by_expiry {
+                    intersect.arg(EXPIRY_INDEX);
+                }
+                intersect.arg("WEIGHTS");
+                intersect.arg(vec![0; filter_keys.len()]);
+                if by_expiry {
+                    intersect.arg(1);
+                }
+
+                let mut pipe = redis::pipe();
+                pipe.add_command(intersect).ignore();
+                pipe.expire(&temp_key, QUERY_KEY_TTL_SECONDS).ignore();
+                pipe.query_async::<()>(&mut conn)
+                    .await
+                    .map_err(CertAgentError::Redis)?;
+
+                temp_keys.push(temp_key.clone());
+                temp_key
+            }
+        };
+
+        let mut certificates = Vec::with_capacity(page_size);
+        let mut exhausted = false;
+        while certificates.len() < page_size {
+            let wanted = page_size - certificates.len();
+            let batch = if by_expiry {
+                range_by_expiry(&mut conn, &driving_key, query, cursor.as_ref(), wanted).await?
+            } else {
+                range_by_issue(&mut conn, &driving_key, query, cursor.as_ref(), wanted).await?
+            };
+            exhausted = batch.len() < wanted;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            cursor = Some(last.clone());
+
+            let members: Vec<&String> = batch.iter().map(|(member, _)| member).collect();
+            let records = load_members(&mut conn, &members, &driving_key).await?;
+            certificates.extend(
+                records
+                    .into_iter()
+                    .filter(|cert_record| query.matches(cert_record)),
+            );
+
+            if exhausted {
+                break;
+            }
+        }
+
+        if !temp_keys.is_empty() {
+            conn.del::<_, ()>(&temp_keys)
+                .await
+                .map_err(CertAgentError::Redis)?;
+        }
+
+        let next_page_token = match cursor {
+            Some(cursor) if !exhausted => Some(encode_page_token(query.sort, &cursor)),
+            _ => None,
+        };
+
+        Ok(CertificatePage {
+            certificates,
+            next_page_token,
+        })
     }
 
-    pub async fn list_certificates(
-        &self,
-        status_filter: Option<&str>,
-    ) -> Result<Vec<CertificateRecord>> {
+    /// Removes index entries and serial lookups left behind by records that
+    /// expired from Redis, and finds records no index knows about.
+    async fn repair(&self) -> Result<StoreRepair> {
         let mut conn = self.get_connection().await?;
-        let certificate_ids: Vec<String> = conn
-            .smembers("certs:all")
+        let mut repair = StoreRepair::default();
+
+        let mut index_keys: Vec<String> = Vec::new();
+        let mut record_keys: Vec<String> = Vec::new();
+        let mut serial_keys: Vec<String> = Vec::new();
+        let mut iter: redis::AsyncIter<String> = conn
+            .scan_match("cert*:*")
             .await
             .map_err(CertAgentError::Redis)?;
+        while let Some(key) = iter.next_item().await {
+            if let Some(id) = key.strip_prefix("cert:") {
+                if id.starts_with("serial:") {
+                    serial_keys.push(key);
+                } else if !id.contains(':') {
+                    record_keys.push(key);
+                }
+            } else if key.starts_with("certs:")
+                && key != INDEX_VERSION_KEY
+                && !key.starts_with("certs:query:")
+            {
+                index_keys.push(key);
+            }
+        }
+        drop(iter);
 
-        let mut certificates = Vec::new();
-
-        for cert_id in certificate_ids {
-            let key = format!("cert:{}", cert_id);
-            let value: Option<String> = conn.get(&key).await.map_err(CertAgentError::Redis)?;
-
-            if let Some(v) = value {
-                let cert_record: CertificateRecord = serde_json::from_str(&v)?;
+        // Every index is a sorted set of members naming a certificate ID,
+        // except certs:all, which holds the IDs themselves
+        for index in &index_keys {
+            let members: Vec<String> = if index == "certs:all" {
+                conn.smembers(index).await
+            } else {
+                conn.zrange(index, 0, -1).await
+            }
+            .map_err(CertAgentError::Redis)?;
 
-                if let Some(status) = status_filter {
-                    if cert_record.status == status {
-                        certificates.push(cert_record);
+            let mut dangling = Vec::new();
+            for batch in members.chunks(LIST_BATCH_SIZE) {
+                let ids = batch.iter().map(|member| {
+                    if index == "certs:all" {
+                        member.as_str()
+                    } else if index == CN_PREFIX_INDEX {
+                        member
+                            .split_once('\0')
+                            .map_or(member.as_str(), |(_, member)| member_certificate_id(member))
+                    } else {
+                        member_certificate_id(member)
                     }
+                });
+                let exists = records_exist(&mut conn, ids).await?;
+                dangling.extend(
+                    batch
+                        .iter()
+                        .zip(exists)
+                        .filter(|(_, exists)| !exists)
+                        .map(|(member, _)| member),
+                );
+            }
+
+            if !dangling.is_empty() {
+                repair.removed_entries += dangling.len();
+                if index == "certs:all" {
+                    conn.srem::<_, _, ()>(index, &dangling).await
                 } else {
-                    certificates.push(cert_record);
+                    conn.zrem::<_, _, ()>(index, &dangling).await
                 }
+                .map_err(CertAgentError::Redis)?;
+            }
+        }
+
+        for batch in serial_keys.chunks(LIST_BATCH_SIZE) {
+            let ids: Vec<Option<String>> = redis::cmd("MGET")
+                .arg(batch)
+                .query_async(&mut conn)
+                .await
+                .map_err(CertAgentError::Redis)?;
+            let exists =
+                records_exist(&mut conn, ids.iter().map(|id| id.as_deref().unwrap_or(""))).await?;
+            let dangling: Vec<&String> = batch
+                .iter()
+                .zip(exists)
+                .filter(|(_, exists)| !exists)
+                .map(|(key, _)| key)
+                .collect();
+            if !dangling.is_empty() {
+                repair.removed_entries += dangling.len();
+                conn.del::<_, ()>(&dangling)
+                    .await
+                    .map_err(CertAgentError::Redis)?;
             }
         }
 
-        Ok(certificates)
+        for batch in record_keys.chunks(LIST_BATCH_SIZE) {
+            let ids: Vec<&str> = batch.iter().map(|key| &key["cert:".len()..]).collect();
+            let mut pipe = redis::pipe();
+            for id in &ids {
+                pipe.sismember("certs:all", id);
+            }
+            let indexed: Vec<bool> = pipe
+                .query_async(&mut conn)
+                .await
+                .map_err(CertAgentError::Redis)?;
+            repair.orphaned_records.extend(
+                ids.iter()
+                    .zip(indexed)
+                    .filter(|(_, indexed)| !indexed)
+                    .map(|(id, _)| id.to_string()),
+            );
+        }
+
+        Ok(repair)
     }
 
-    pub async fn get_expiring_certificates(
+    /// Active certificates expiring within `threshold_days`, soonest first.
+    async fn get_expiring_certificates(
         &self,
         threshold_days: u32,
     ) -> Result<Vec<CertificateRecord>> {
-        let all_certs = self.list_certificates(Some("active")).await?;
         let threshold_seconds = (threshold_days as i64) * 24 * 60 * 60;
         let current_time = chrono::Utc::now().timestamp();
 
-        let expiring_certs = all_certs
-            .into_iter()
-            .filter(|cert| {
-                let time_until_expiry = cert.expires_at - current_time;
-                time_until_expiry > 0 && time_until_expiry <= threshold_seconds
-            })
-            .collect();
+        self.active_expiring_between(
+            format!("({}", current_time),
+            (current_time + threshold_seconds).to_string(),
+        )
+        .await
+    }
+
+    /// Certificates still marked active although `expires_at` has passed.
+    async fn get_lapsed_certificates(&self) -> Result<Vec<CertificateRecord>> {
+        let current_time = chrono::Utc::now().timestamp();
+        self.active_expiring_between("-inf".to_string(), current_time.to_string())
+            .await
+    }
 
-        Ok(expiring_certs)
+    /// Marks an active certificate whose `expires_at` has passed as expired.
+    /// The record is watched while it is rewritten, so a concurrent
+    /// revocation or renewal wins and `false` is returned.
+    async fn expire_certificate(&self, certificate_id: &str) -> Result<bool> {
+        let current_time = chrono::Utc::now().timestamp();
+        self.update_watched(
+            certificate_id,
+            |cert_record| {
+                if cert_record.status != "active" || cert_record.expires_at > current_time {
+                    return false;
+                }
+                cert_record.status = "expired".to_string();
+                true
+            },
+            redis::pipe(),
+        )
+        .await
     }
 
-    #[allow(dead_code)]
-    pub async fn delete_certificate(&self, certificate_id: &str) -> Result<()> {
+    async fn delete_certificate(&self, certificate_id: &str) -> Result<()> {
         let mut conn = self.get_connection().await?;
         let key = format!("cert:{}", certificate_id);
 
+        // Remove serial and listing index entries
+        if let Some(cert_record) = self.get_certificate(certificate_id).await? {
+            if !cert_record.serial_number.is_empty() {
+                let _: () = conn
+                    .del(format!("cert:serial:{}", cert_record.serial_number))
+                    .await
+                    .map_err(CertAgentError::Redis)?;
+            }
+
+            let mut pipe = redis::pipe();
+            unindex_record(&mut pipe, &cert_record);
+            pipe.query_async::<()>(&mut conn)
+                .await
+                .map_err(CertAgentError::Redis)?;
+        }
+
         // Remove from main storage
         let _: () = conn.del(&key).await.map_err(CertAgentError::Redis)?;
 
@@ -172,12 +713,437 @@ impl RedisClient {
         Ok(())
     }
 
+    // CRL storage
+    async fn next_crl_number(&self) -> Result<u64> {
+        let mut conn = self.get_connection().await?;
+        conn.incr("crl:number", 1)
+            .await
+            .map_err(CertAgentError::Redis)
+    }
+
+    async fn store_crl(&self, issuer_key_id: &str, crl_der: &[u8]) -> Result<()> {
+        let mut conn = self.get_connection().await?;
+        conn.set::<_, _, ()>(format!("crl:current:{}", issuer_key_id), crl_der)
+            .await
+            .map_err(CertAgentError::Redis)
+    }
+
+    async fn get_crl(&self, issuer_key_id: &str) -> Result<Option<Vec<u8>>> {
+        let mut conn = self.get_connection().await?;
+        conn.get(format!("crl:current:{}", issuer_key_id))
+            .await
+            .map_err(CertAgentError::Redis)
+    }
+
+    // CA rotation state
+    async fn get_ca_rotation(&self) -> Result<Option<CaRotationRecord>> {
+        let mut conn = self.get_connection().await?;
+        let value: Option<String> = conn
+            .get("ca:rotation")
+            .await
+            .map_err(CertAgentError::Redis)?;
+
+        match value {
+            Some(v) => Ok(Some(serde_json::from_str(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn store_ca_rotation(&self, rotation: &CaRotationRecord) -> Result<()> {
+        let mut conn = self.get_connection().await?;
+        let value = serde_json::to_string(rotation)?;
+        conn.set::<_, _, ()>("ca:rotation", value)
+            .await
+            .map_err(CertAgentError::Redis)
+    }
+
+    async fn delete_ca_rotation(&self) -> Result<()> {
+        let mut conn = self.get_connection().await?;
+        conn.del::<_, ()>("ca:rotation")
+            .await
+            .map_err(CertAgentError::Redis)
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        ttl_seconds: u64,
+    ) -> Result<Option<IdempotencyRecord>> {
+        let mut conn = self.get_connection().await?;
+        let key = format!("idempotency:{}", key);
+        let value = serde_json::to_string(record)?;
+
+        loop {
+            let claimed: Option<String> = redis::cmd("SET")
+                .arg(&key)
+                .arg(&value)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl_seconds)
+                .query_async(&mut conn)
+                .await
+                .map_err(CertAgentError::Redis)?;
+            if claimed.is_some() {
+                return Ok(None);
+            }
+
+            // Released or expired in between: try to claim it again
+            let existing: Option<String> = conn.get(&key).await.map_err(CertAgentError::Redis)?;
+            if let Some(existing) = existing {
+                return Ok(Some(serde_json::from_str(&existing)?));
+            }
+        }
+    }
+
+    async fn store_idempotency_record(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        ttl_seconds: u64,
+    ) -> Result<()> {
+        let mut conn = self.get_connection().await?;
+        conn.set_ex::<_, _, ()>(
+            format!("idempotency:{}", key),
+            serde_json::to_string(record)?,
+            ttl_seconds,
+        )
+        .await
+        .map_err(CertAgentError::Redis)
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<()> {
+        let mut conn = self.get_connection().await?;
+        conn.del::<_, ()>(format!("idempotency:{}", key))
+            .await
+            .map_err(CertAgentError::Redis)
+    }
+
     // Pub/Sub for real-time notifications
-    pub async fn publish_event(&self, event: &str, data: &str) -> Result<()> {
+    async fn publish_event(&self, event: &str, data: &str) -> Result<()> {
         let mut conn = self.get_connection().await?;
-        conn.publish::<_, _, ()>("cert_events", format!("{}:{}", event, data))
+        conn.publish::<_, _, ()>(EVENTS_CHANNEL, event_message(event, data))
             .await
             .map_err(CertAgentError::Redis)?;
         Ok(())
     }
+
+    async fn subscribe_events(&self) -> Result<EventStream> {
+        let mut pubsub = self
+            .client
+            .get_async_pubsub()
+            .await
+            .map_err(CertAgentError::Redis)?;
+        pubsub
+            .subscribe(EVENTS_CHANNEL)
+            .await
+            .map_err(CertAgentError::Redis)?;
+
+        Ok(pubsub
+            .into_on_message()
+            .filter_map(|msg| async move {
+                let payload: String = msg.get_payload().ok()?;
+                let (event, data) = payload.split_once(':')?;
+                Some((event.to_string(), data.to_string()))
+            })
+            .boxed())
+    }
+}
+
+/// Secondary index sets holding the candidates for the query's equality
+/// filters.
+fn query_index_keys(query: &CertificateQuery) -> Vec<String> {
+    let mut keys = Vec::new();
+    keys.extend(query.status.as_deref().map(status_index));
+    keys.extend(query.common_name.as_deref().map(cn_index));
+    keys.extend(query.dns_name.as_deref().map(dns_index));
+    keys.extend(query.ip_address.as_deref().map(ip_index));
+    keys.extend(
+        query
+            .metadata
+            .iter()
+            .map(|(key, value)| metadata_index(key, value)),
+    );
+    keys.extend(query.issuer_key_id.as_deref().map(issuer_index));
+    keys.extend(query.profile.as_deref().map(profile_index));
+    keys.extend(query.lineage_id.as_deref().map(lineage_index));
+    keys
+}
+
+fn status_index(status: &str) -> String {
+    format!("certs:status:{}", status)
+}
+
+fn index_member(cert_record: &CertificateRecord) -> String {
+    format!(
+        "{:020}:{}",
+        cert_record.issued_at.max(0),
+        cert_record.certificate_id
+    )
+}
+
+fn member_certificate_id(member: &str) -> &str {
+    member.split_once(':').map_or(member, |(_, id)| id)
+}
+
+fn temp_query_key() -> String {
+    format!("certs:query:{}", Uuid::new_v4())
+}
+
+/// Secondary index sets `cert_record` belongs to, besides the status index.
+fn search_index_keys(cert_record: &CertificateRecord) -> Vec<String> {
+    let mut keys = vec![cn_index(&cert_record.common_name)];
+    keys.extend(cert_record.dns_names.iter().map(|name| dns_index(name)));
+    keys.extend(cert_record.ip_addresses.iter().map(|ip| ip_index(ip)));
+    keys.extend(
+        cert_record
+            .metadata
+            .iter()
+            .map(|(key, value)| metadata_index(key, value)),
+    );
+    if !cert_record.issuer_key_id.is_empty() {
+        keys.push(issuer_index(&cert_record.issuer_key_id));
+    }
+    if !cert_record.profile.is_empty() {
+        keys.push(profile_index(&cert_record.profile));
+    }
+    keys.push(lineage_index(cert_record.lineage()));
+    keys
+}
+
+fn cn_index(common_name: &str) -> String {
+    format!("certs:cn:{}", common_name.to_lowercase())
+}
+
+fn cn_prefix_entry(cert_record: &CertificateRecord) -> String {
+    format!(
+        "{}\0{}",
+        cert_record.common_name.to_lowercase(),
+        index_member(cert_record)
+    )
+}
+
+fn dns_index(dns_name: &str) -> String {
+    format!("certs:dns:{}", dns_name.to_ascii_lowercase())
+}
+
+fn ip_index(ip_address: &str) -> String {
+    format!("certs:ip:{}", canonical_ip(ip_address))
+}
+
+fn metadata_index(key: &str, value: &str) -> String {
+    format!("certs:meta:{}:{}", key, value)
+}
+
+fn issuer_index(issuer_key_id: &str) -> String {
+    format!("certs:issuer:{}", issuer_key_id.to_ascii_uppercase())
+}
+
+/// Pub/sub message carrying `event` and its data.
+fn event_message(event: &str, data: &str) -> String {
+    format!("{}:{}", event, data)
+}
+
+fn profile_index(profile: &str) -> String {
+    format!("certs:profile:{}", profile)
+}
+
+fn lineage_index(lineage_id: &str) -> String {
+    format!("certs:lineage:{}", lineage_id)
+}
+
+/// Adds `cert_record` to the listing and search indexes, moving it out of
+/// the index of `previous_status` when its status changed.
+fn index_record(
+    pipe: &mut redis::Pipeline,
+    cert_record: &CertificateRecord,
+    previous_status: Option<&str>,
+) {
+    let member = index_member(cert_record);
+    if let Some(previous_status) = previous_status.filter(|status| *status != cert_record.status) {
+        pipe.zrem(status_index(previous_status), &member).ignore();
+    }
+    pipe.zadd(CERT_INDEX, &member, 0).ignore();
+    pipe.zadd(EXPIRY_INDEX, &member, cert_record.expires_at)
+        .ignore();
+    pipe.zadd(status_index(&cert_record.status), &member, 0)
+        .ignore();
+    if cert_record.status == "active" {
+        pipe.zadd(ACTIVE_EXPIRY_INDEX, &member, cert_record.expires_at)
+            .ignore();
+    } else {
+        pipe.zrem(ACTIVE_EXPIRY_INDEX, &member).ignore();
+    }
+    pipe.zadd(CN_PREFIX_INDEX, cn_prefix_entry(cert_record), 0)
+        .ignore();
+    for key in search_index_keys(cert_record) {
+        pipe.zadd(key, &member, 0).ignore();
+    }
+}
+
+fn unindex_record(pipe: &mut redis::Pipeline, cert_record: &CertificateRecord) {
+    let member = index_member(cert_record);
+    pipe.zrem(CERT_INDEX, &member).ignore();
+    pipe.zrem(EXPIRY_INDEX, &member).ignore();
+    pipe.zrem(ACTIVE_EXPIRY_INDEX, &member).ignore();
+    pipe.zrem(status_index(&cert_record.status), &member)
+        .ignore();
+    pipe.zrem(CN_PREFIX_INDEX, cn_prefix_entry(cert_record))
+        .ignore();
+    for key in search_index_keys(cert_record) {
+        pipe.zrem(key, &member).ignore();
+    }
+}
+
+/// Whether the record for each of `certificate_ids` exists.
+async fn records_exist<'a>(
+    conn: &mut ConnectionManager,
+    certificate_ids: impl Iterator<Item = &'a str>,
+) -> Result<Vec<bool>> {
+    let mut pipe = redis::pipe();
+    for id in certificate_ids {
+        pipe.exists(format!("cert:{}", id));
+    }
+    pipe.query_async(conn).await.map_err(CertAgentError::Redis)
+}
+
+/// Loads the records behind index `members` with one MGET. Members whose
+/// record expired from Redis are dropped from the ordered indexes and from
+/// `index`, the set they were read from.
+async fn load_members(
+    conn: &mut ConnectionManager,
+    members: &[&String],
+    index: &str,
+) -> Result<Vec<CertificateRecord>> {
+    if members.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let keys: Vec<String> = members
+        .iter()
+        .map(|member| format!("cert:{}", member_certificate_id(member)))
+        .collect();
+    let values: Vec<Option<String>> = redis::cmd("MGET")
+        .arg(&keys)
+        .query_async(conn)
+        .await
+        .map_err(CertAgentError::Redis)?;
+
+    let mut records = Vec::with_capacity(members.len());
+    let mut stale = Vec::new();
+    for (member, value) in members.iter().zip(values) {
+        match value {
+            Some(v) => records.push(serde_json::from_str(&v)?),
+            None => stale.push(*member),
+        }
+    }
+
+    if !stale.is_empty() {
+        let mut pipe = redis::pipe();
+        for key in [CERT_INDEX, EXPIRY_INDEX, ACTIVE_EXPIRY_INDEX, index] {
+            pipe.zrem(key, &stale).ignore();
+        }
+        pipe.query_async::<()>(conn)
+            .await
+            .map_err(CertAgentError::Redis)?;
+    }
+
+    Ok(records)
+}
+
+/// Next members of a zero-score set in issue order, after `cursor` and
+/// within the query's issue time range.
+async fn range_by_issue(
+    conn: &mut ConnectionManager,
+    key: &str,
+    query: &CertificateQuery,
+    cursor: Option<&(String, i64)>,
+    count: usize,
+) -> Result<Vec<(String, i64)>> {
+    let lowest = query
+        .issued_after
+        .map_or("-".to_string(), |time| format!("[{:020}", time.max(0)));
+    let highest = query
+        .issued_before
+        .map_or("+".to_string(), |time| format!("({:020}", time.max(0)));
+    let after_cursor = cursor.map(|(member, _)| format!("({}", member));
+
+    let members: Vec<String> = if query.sort == ListSort::IssuedDesc {
+        let max = after_cursor.unwrap_or(highest);
+        conn.zrevrangebylex_limit(key, max, lowest, 0, count as isize)
+            .await
+    } else {
+        let min = after_cursor.unwrap_or(lowest);
+        conn.zrangebylex_limit(key, min, highest, 0, count as isize)
+            .await
+    }
+    .map_err(CertAgentError::Redis)?;
+
+    Ok(members.into_iter().map(|member| (member, 0)).collect())
+}
+
+/// Next members of an expiry-scored set in expiry order, after `cursor` and
+/// within the query's expiry time range. Members expiring at the same second
+/// as the cursor are ordered by member.
+async fn range_by_expiry(
+    conn: &mut ConnectionManager,
+    key: &str,
+    query: &CertificateQuery,
+    cursor: Option<&(String, i64)>,
+    count: usize,
+) -> Result<Vec<(String, i64)>> {
+    let descending = query.sort == ListSort::ExpiresDesc;
+    let lowest = query
+        .expires_after
+        .map_or("-inf".to_string(), |time| time.to_string());
+    let highest = query
+        .expires_before
+        .map_or("+inf".to_string(), |time| format!("({}", time));
+
+    let mut entries = Vec::new();
+    let (from, to) = match cursor {
+        Some((member, score)) => {
+            let ties: Vec<String> = conn
+                .zrangebyscore(key, score, score)
+                .await
+                .map_err(CertAgentError::Redis)?;
+            let mut ties: Vec<String> = ties
+                .into_iter()
+                .filter(|tie| {
+                    if descending {
+                        tie < member
+                    } else {
+                        tie > member
+                    }
+                })
+                .collect();
+            if descending {
+                ties.reverse();
+            }
+            entries.extend(ties.into_iter().take(count).map(|tie| (tie, *score)));
+
+            if descending {
+                (format!("({}", score), lowest)
+            } else {
+                (format!("({}", score), highest)
+            }
+        }
+        None if descending => (highest, lowest),
+        None => (lowest, highest),
+    };
+
+    if entries.len() < count {
+        let remaining = (count - entries.len()) as isize;
+        let more: Vec<(String, i64)> = if descending {
+            conn.zrevrangebyscore_limit_withscores(key, from, to, 0, remaining)
+                .await
+        } else {
+            conn.zrangebyscore_limit_withscores(key, from, to, 0, remaining)
+                .await
+        }
+        .map_err(CertAgentError::Redis)?;
+        entries.extend(more);
+    }
+
+    Ok(entries)
 }
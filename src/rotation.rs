@@ -0,0 +1,693 @@
+use crate::certificate::{certificate_fingerprint, CaCredentials, CertificateManager};
+use crate::config::RotationConfig;
+use crate::error::{CertAgentError, Result};
+use crate::store::{CaRotationRecord, CertificateRecord, CertificateStore};
+use chrono::Utc;
+use openssl::x509::X509;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::fs;
+use tracing::{info, warn};
+
+/// Snapshot of the rotation workflow reported by `GetCaRotationStatus`.
+#[derive(Debug, Clone)]
+pub struct RotationStatus {
+    /// "idle", "staged" or "reissuing"
+    pub phase: String,
+    pub active_ca_fingerprint: String,
+    pub staged_ca_fingerprint: Option<String>,
+    pub previous_ca_fingerprint: Option<String>,
+    /// Every CA certificate relying parties should currently trust
+    pub trust_bundle_pem: Vec<String>,
+    /// Active certificates not yet issued by the active CA
+    pub remaining_certificates: usize,
+    pub staged_at: Option<i64>,
+    pub activated_at: Option<i64>,
+    pub reissue_deadline: Option<i64>,
+}
+
+/// Replaces the issuing CA without invalidating issued certificates at once.
+///
+/// 1. `stage`: a new CA is loaded (or generated) and added to the trust bundle.
+/// 2. `activate`: issuance switches to the new CA and the watcher re-issues
+///    every active certificate over `RotationConfig::reissue_window_hours`.
+/// 3. `retire`: the previous CA leaves the trust bundle.
+///
+/// The state lives in the certificate store and the activated CA is written over the
+/// configured CA paths, so a restart resumes in the same phase.
+#[derive(Debug, Clone)]
+pub struct CaRotation {
+    cert_manager: CertificateManager,
+    store: Arc<dyn CertificateStore>,
+    config: RotationConfig,
+}
+
+impl CaRotation {
+    pub fn new(
+        cert_manager: CertificateManager,
+        store: Arc<dyn CertificateStore>,
+        config: RotationConfig,
+    ) -> Self {
+        Self {
+            cert_manager,
+            store,
+            config,
+        }
+    }
+
+    /// Stages the CA at `cert_path`/`key_path`, or generates a self-signed
+    /// one next to the configured CA when no paths are given.
+    pub async fn stage(
+        &self,
+        cert_path: Option<String>,
+        key_path: Option<String>,
+        chain_path: Option<String>,
+    ) -> Result<RotationStatus> {
+        if let Some(rotation) = self.store.get_ca_rotation().await? {
+            return Err(CertAgentError::InvalidState(format!(
+                "CA rotation already in progress (phase: {})",
+                rotation.phase
+            )));
+        }
+
+        let config = self.cert_manager.config();
+        let (staged, cert_path, key_path) = match (cert_path, key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                let staged = CaCredentials::load(
+                    &cert_path,
+                    &key_path,
+                    chain_path.as_deref(),
+                    self.cert_manager.keys(),
+                )
+                .await?;
+                (staged, cert_path, key_path)
+            }
+            (None, None) => {
+                if config.ca_chain_path.is_some() {
+                    return Err(CertAgentError::InvalidRequest(
+                        "A new intermediate CA signed by the root must be provided".to_string(),
+                    ));
+                }
+
+                let staged = CaCredentials::generate_self_signed(
+                    config,
+                    self.cert_manager.signature_algorithm(),
+                )?;
+                let cert_path = format!("{}.next", config.ca_cert_path);
+                let key_path = format!("{}.next", config.ca_key_path);
+                staged
+                    .write(&cert_path, &key_path, None, self.cert_manager.keys())
+                    .await?;
+                (staged, cert_path, key_path)
+            }
+            _ => {
+                return Err(CertAgentError::InvalidRequest(
+                    "CA certificate and key paths must be given together".to_string(),
+                ))
+            }
+        };
+
+        // Activation copies the staged files over the configured ones, so the
+        // staged CA must have the same shape
+        if config.ca_chain_path.is_some() != chain_path.is_some() {
+            return Err(CertAgentError::InvalidRequest(
+                "A CA chain must be staged exactly when ca_chain_path is configured".to_string(),
+            ));
+        }
+        if staged.key_id()? == self.cert_manager.ca().key_id()? {
+            return Err(CertAgentError::InvalidRequest(
+                "Staged CA uses the same key as the active CA".to_string(),
+            ));
+        }
+
+        let rotation = CaRotationRecord {
+            phase: "staged".to_string(),
+            staged_cert_path: cert_path,
+            staged_key_path: key_path,
+            staged_chain_path: chain_path,
+            staged_ca_pem: staged.chain_pem()?,
+            previous_ca_pem: Vec::new(),
+            staged_at: Utc::now().timestamp(),
+            activated_at: None,
+            reissue_deadline: None,
+        };
+        self.store.store_ca_rotation(&rotation).await?;
+
+        let fingerprint = certificate_fingerprint(&staged.cert)?;
+        info!(
+            "Staged new CA {:?} ({})",
+            staged.cert.subject_name(),
+            fingerprint
+        );
+        self.store
+            .publish_event("ca_rotation_staged", &fingerprint)
+            .await?;
+
+        self.status().await
+    }
+
+    /// Switches issuance to the staged CA and starts re-issuance.
+    pub async fn activate(&self) -> Result<RotationStatus> {
+        let mut rotation = match self.store.get_ca_rotation().await? {
+            Some(rotation) if rotation.phase == "staged" => rotation,
+            Some(rotation) => {
+                return Err(CertAgentError::InvalidState(format!(
+                    "Cannot activate CA rotation in phase: {}",
+                    rotation.phase
+                )))
+            }
+            None => {
+                return Err(CertAgentError::InvalidState(
+                    "No CA rotation is staged".to_string(),
+                ))
+            }
+        };
+
+        let staged = CaCredentials::load(
+            &rotation.staged_cert_path,
+            &rotation.staged_key_path,
+            rotation.staged_chain_path.as_deref(),
+            self.cert_manager.keys(),
+        )
+        .await?;
+        let config = self.cert_manager.config();
+
+        // Record the CA being replaced before touching any file, so that an
+        // activation interrupted by a crash resumes with it
+        if rotation.previous_ca_pem.is_empty() {
+            rotation.previous_ca_pem = self.cert_manager.ca().chain_pem()?;
+            self.store.store_ca_rotation(&rotation).await?;
+        }
+
+        // Keep the previous CA files next to the configured ones, then make
+        // the staged CA the one loaded on restart. Once an interrupted
+        // activation has written the staged CA, the previous files already
+        // hold the replaced CA and must not be overwritten.
+        let configured = X509::from_pem(&fs::read(&config.ca_cert_path).await?)?;
+        if !configured.public_key()?.public_eq(&staged.key) {
+            let mut paths = vec![&config.ca_cert_path, &config.ca_key_path];
+            paths.extend(config.ca_chain_path.as_ref());
+            for path in paths {
+                if Path::new(path).exists() {
+                    fs::copy(path, previous_path(path)).await?;
+                }
+            }
+        }
+        staged
+            .write(
+                &config.ca_cert_path,
+                &config.ca_key_path,
+                config.ca_chain_path.as_deref(),
+                self.cert_manager.keys(),
+            )
+            .await?;
+
+        let fingerprint = certificate_fingerprint(&staged.cert)?;
+        self.cert_manager.replace_ca(staged);
+
+        let now = Utc::now().timestamp();
+        rotation.phase = "reissuing".to_string();
+        rotation.activated_at = Some(now);
+        rotation.reissue_deadline = Some(now + self.config.reissue_window_hours as i64 * 60 * 60);
+        self.store.store_ca_rotation(&rotation).await?;
+
+        info!(
+            "Activated CA {}; re-issuing certificates over {} hours",
+            fingerprint, self.config.reissue_window_hours
+        );
+        self.store
+            .publish_event("ca_rotation_activated", &fingerprint)
+            .await?;
+
+        // Start the new CA's CRL right away rather than at the next refresh
+        if let Err(e) = self.cert_manager.publish_crl().await {
+            warn!("Failed to publish CRL for the new CA: {}", e);
+        }
+
+        self.status().await
+    }
+
+    /// Ends the rotation. While staged this discards the staged CA; while
+    /// re-issuing it drops the previous CA from the trust bundle, refusing
+    /// while certificates still depend on it unless `force` is set.
+    pub async fn retire(&self, force: bool) -> Result<RotationStatus> {
+        let rotation = self.store.get_ca_rotation().await?.ok_or_else(|| {
+            CertAgentError::InvalidState("No CA rotation in progress".to_string())
+        })?;
+
+        if rotation.phase == "staged" {
+            // The configured CA may already be the staged one
+            if !rotation.previous_ca_pem.is_empty() {
+                return Err(CertAgentError::InvalidState(
+                    "CA activation was interrupted; activate the rotation again".to_string(),
+                ));
+            }
+            self.store.delete_ca_rotation().await?;
+            info!("Cancelled staged CA rotation");
+            self.store
+                .publish_event("ca_rotation_cancelled", &rotation.staged_cert_path)
+                .await?;
+            return self.status().await;
+        }
+
+        // Retiring would leave clients that only trust the new CA unable to
+        // reach an agent still serving a certificate from the previous one
+        let serving: Vec<_> = self
+            .issued_by_previous_ca()
+            .await?
+            .into_iter()
+            .filter(|cert| cert.tls_bootstrap)
+            .map(|cert| cert.certificate_id)
+            .collect();
+        if !serving.is_empty() {
+            return Err(CertAgentError::InvalidState(format!(
+                "gRPC serving certificates {} are still issued by the previous CA; \
+                 the watcher re-issues them, or revoke those of agents no longer running",
+                serving.join(", ")
+            )));
+        }
+
+        let remaining = self.pending_reissue().await?.len();
+        if remaining > 0 && !force {
+            return Err(CertAgentError::InvalidState(format!(
+                "{} active certificates are still issued by the previous CA",
+                remaining
+            )));
+        }
+
+        self.store.delete_ca_rotation().await?;
+
+        let fingerprint = match rotation.previous_ca_pem.first() {
+            Some(pem) => pem_fingerprint(pem)?,
+            None => String::new(),
+        };
+        info!(
+            "Retired previous CA {} ({} certificates not re-issued)",
+            fingerprint, remaining
+        );
+        self.store
+            .publish_event("ca_rotation_retired", &fingerprint)
+            .await?;
+
+        self.status().await
+    }
+
+    pub async fn status(&self) -> Result<RotationStatus> {
+        let ca = self.cert_manager.ca();
+        let rotation = self.store.get_ca_rotation().await?;
+
+        let remaining_certificates = match rotation {
+            Some(ref rotation) if rotation.phase == "reissuing" => {
+                self.pending_reissue().await?.len()
+            }
+            _ => 0,
+        };
+
+        let fingerprint_of = |chain: &[String]| -> Result<Option<String>> {
+            chain.first().map(|pem| pem_fingerprint(pem)).transpose()
+        };
+
+        Ok(RotationStatus {
+            phase: rotation
+                .as_ref()
+                .map(|rotation| rotation.phase.clone())
+                .unwrap_or_else(|| "idle".to_string()),
+            active_ca_fingerprint: certificate_fingerprint(&ca.cert)?,
+            staged_ca_fingerprint: match rotation {
+                Some(ref rotation) if rotation.phase == "staged" => {
+                    fingerprint_of(&rotation.staged_ca_pem)?
+                }
+                _ => None,
+            },
+            previous_ca_fingerprint: match rotation {
+                Some(ref rotation) => fingerprint_of(&rotation.previous_ca_pem)?,
+                None => None,
+            },
+            trust_bundle_pem: self.trust_bundle(rotation.as_ref())?,
+            remaining_certificates,
+            staged_at: rotation.as_ref().map(|rotation| rotation.staged_at),
+            activated_at: rotation.as_ref().and_then(|rotation| rotation.activated_at),
+            reissue_deadline: rotation
+                .as_ref()
+                .and_then(|rotation| rotation.reissue_deadline),
+        })
+    }
+
+    /// CA certificates to trust: the active CA chain plus the staged or
+    /// previous one while a rotation is in progress, without duplicates.
+    pub async fn trust_bundle_pem(&self) -> Result<Vec<String>> {
+        let rotation = self.store.get_ca_rotation().await?;
+        self.trust_bundle(rotation.as_ref())
+    }
+
+    fn trust_bundle(&self, rotation: Option<&CaRotationRecord>) -> Result<Vec<String>> {
+        let mut bundle = self.cert_manager.ca().chain_pem()?;
+        if let Some(rotation) = rotation {
+            // Both while an interrupted activation leaves either CA active
+            for pem in rotation
+                .staged_ca_pem
+                .iter()
+                .chain(&rotation.previous_ca_pem)
+            {
+                if !bundle.contains(pem) {
+                    bundle.push(pem.clone());
+                }
+            }
+        }
+        Ok(bundle)
+    }
+
+    /// Certificates the watcher should re-issue on this tick: an even share of
+    /// the remaining ones so that all are done by the reissue deadline, and
+    /// every remaining one signed from a CSR, for the watcher to ask its
+    /// client to renew.
+    pub async fn reissue_due(&self, check_interval_seconds: u64) -> Result<Vec<CertificateRecord>> {
+        let deadline = match self.store.get_ca_rotation().await? {
+            Some(rotation) if rotation.phase == "reissuing" => {
+                rotation.reissue_deadline.unwrap_or_default()
+            }
+            _ => return Ok(Vec::new()),
+        };
+
+        // Only the client can re-issue a certificate whose key it holds; all
+        // of those are handed back every tick so it is asked to renew
+        let (client_keys, mut pending): (Vec<_>, Vec<_>) = self
+            .pending_reissue()
+            .await?
+            .into_iter()
+            .partition(|cert| cert.client_key);
+        let seconds_left = (deadline - Utc::now().timestamp()).max(0) as u64;
+        let ticks_left = seconds_left.div_ceil(check_interval_seconds.max(1)).max(1);
+        let batch = pending.len().div_ceil(ticks_left as usize);
+
+        // Soonest-expiring first, they would be renewed soonest anyway
+        pending.sort_by_key(|cert| cert.expires_at);
+        pending.truncate(batch);
+        pending.extend(client_keys);
+        Ok(pending)
+    }
+
+    /// Active certificates still issued by the previous CA. Bootstrapped
+    /// serving certificates are not among them: each agent re-issues its own
+    /// once it no longer verifies against the active CA.
+    async fn pending_reissue(&self) -> Result<Vec<CertificateRecord>> {
+        Ok(self
+            .issued_by_previous_ca()
+            .await?
+            .into_iter()
+            .filter(|cert| !cert.tls_bootstrap)
+            .collect())
+    }
+
+    async fn issued_by_previous_ca(&self) -> Result<Vec<CertificateRecord>> {
+        let active_key_id = self.cert_manager.ca().key_id()?;
+        let active = self.cert_manager.list_certificates(Some("active")).await?;
+        Ok(active
+            .into_iter()
+            .filter(|cert| cert.issuer_key_id != active_key_id)
+            .collect())
+    }
+}
+
+/// Where activation keeps the replaced CA's copy of the CA file at `path`.
+pub(crate) fn previous_path(path: &str) -> String {
+    format!("{}.previous", path)
+}
+
+fn pem_fingerprint(pem: &str) -> Result<String> {
+    let cert = X509::from_pem(pem.as_bytes())?;
+    certificate_fingerprint(&cert)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::tests::{request, test_manager, test_manager_with};
+    use crate::config::TlsConfig;
+    use crate::store::MemoryStore;
+    use crate::tls::ServingCertificate;
+    use openssl::x509::{X509Crl, X509};
+    use tempfile::TempDir;
+
+    async fn rotation() -> (CaRotation, Arc<MemoryStore>, TempDir) {
+        let (cert_manager, store, dir) = test_manager().await;
+        let rotation = CaRotation::new(
+            cert_manager,
+            store.clone(),
+            RotationConfig {
+                reissue_window_hours: 1,
+            },
+        );
+        (rotation, store, dir)
+    }
+
+    async fn issue(rotation: &CaRotation, count: usize) {
+        for i in 0..count {
+            rotation
+                .cert_manager
+                .issue_certificate(request(&format!("api{}.example.com", i)))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn trusts_both_cas_during_the_rotation() {
+        let (rotation, _store, _dir) = rotation().await;
+        let original = rotation.cert_manager.ca().chain_pem().unwrap();
+
+        let staged = rotation.stage(None, None, None).await.unwrap();
+        assert_eq!(staged.phase, "staged");
+        assert_eq!(staged.trust_bundle_pem.len(), 2);
+        assert_eq!(staged.trust_bundle_pem[0], original[0]);
+
+        let reissuing = rotation.activate().await.unwrap();
+        assert_eq!(reissuing.phase, "reissuing");
+        assert_ne!(
+            reissuing.active_ca_fingerprint,
+            staged.active_ca_fingerprint
+        );
+        assert_eq!(
+            reissuing.previous_ca_fingerprint,
+            Some(staged.active_ca_fingerprint)
+        );
+        assert_eq!(reissuing.trust_bundle_pem.len(), 2);
+        assert!(reissuing.trust_bundle_pem.contains(&original[0]));
+
+        let retired = rotation.retire(false).await.unwrap();
+        assert_eq!(retired.phase, "idle");
+        assert_eq!(
+            retired.trust_bundle_pem,
+            rotation.cert_manager.ca().chain_pem().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn spreads_reissuance_over_the_window() {
+        let (rotation, _store, _dir) = rotation().await;
+        issue(&rotation, 10).await;
+        assert!(rotation.reissue_due(600).await.unwrap().is_empty());
+
+        rotation.stage(None, None, None).await.unwrap();
+        rotation.activate().await.unwrap();
+
+        // Six ten-minute ticks left in the hour
+        let due = rotation.reissue_due(600).await.unwrap();
+        assert_eq!(due.len(), 2);
+        let remaining = rotation.pending_reissue().await.unwrap();
+        let soonest = remaining.iter().map(|cert| cert.expires_at).min().unwrap();
+        assert_eq!(due[0].expires_at, soonest);
+        assert!(due[0].expires_at <= due[1].expires_at);
+
+        // Everything on the last tick
+        assert_eq!(rotation.reissue_due(3600).await.unwrap().len(), 10);
+    }
+
+    #[tokio::test]
+    async fn retires_the_previous_ca_only_once_everything_is_reissued() {
+        let (rotation, _store, _dir) = rotation().await;
+        issue(&rotation, 2).await;
+        rotation.stage(None, None, None).await.unwrap();
+        rotation.activate().await.unwrap();
+
+        let error = rotation.retire(false).await.unwrap_err();
+        assert!(matches!(error, CertAgentError::InvalidState(_)));
+        assert_eq!(rotation.status().await.unwrap().remaining_certificates, 2);
+
+        for cert in rotation.pending_reissue().await.unwrap() {
+            rotation
+                .cert_manager
+                .renew_certificate(&cert.certificate_id, None, Default::default())
+                .await
+                .unwrap();
+        }
+        assert_eq!(rotation.status().await.unwrap().remaining_certificates, 0);
+        assert_eq!(rotation.retire(false).await.unwrap().phase, "idle");
+    }
+
+    #[tokio::test]
+    async fn forces_retirement_with_certificates_pending() {
+        let (rotation, _store, _dir) = rotation().await;
+        issue(&rotation, 1).await;
+        rotation.stage(None, None, None).await.unwrap();
+        rotation.activate().await.unwrap();
+
+        assert!(rotation.retire(false).await.is_err());
+        assert_eq!(rotation.retire(true).await.unwrap().phase, "idle");
+    }
+
+    #[tokio::test]
+    async fn retires_only_once_the_serving_certificate_is_reissued() {
+        let (rotation, store, dir) = rotation().await;
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let tls = TlsConfig {
+            cert_file: path("grpc/server.crt"),
+            key_file: path("grpc/server.key"),
+            ca_file: None,
+            client_auth_optional: false,
+            bootstrap: true,
+            server_names: vec!["agent.example.com".to_string()],
+        };
+        let serving = ServingCertificate::load(&tls, &rotation.cert_manager)
+            .await
+            .unwrap();
+        rotation.stage(None, None, None).await.unwrap();
+        rotation.activate().await.unwrap();
+
+        // Not counted among the certificates left to re-issue, yet blocking
+        assert_eq!(rotation.status().await.unwrap().remaining_certificates, 0);
+        let error = rotation.retire(true).await.unwrap_err();
+        assert!(matches!(error, CertAgentError::InvalidState(_)));
+
+        serving.refresh().await.unwrap();
+        let served = X509::from_pem(&std::fs::read(&tls.cert_file).unwrap()).unwrap();
+        let active_ca = rotation.cert_manager.ca().cert.public_key().unwrap();
+        assert!(served.verify(&active_ca).unwrap());
+        let active = store.list_certificates(Some("active")).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert!(active[0].tls_bootstrap);
+
+        assert_eq!(rotation.retire(false).await.unwrap().phase, "idle");
+    }
+
+    #[tokio::test]
+    async fn publishes_a_crl_per_ca_while_reissuing() {
+        let (cert_manager, store, _dir) = test_manager_with(|config| {
+            config.revocation.base_url = Some("http://ca.example.com".to_string());
+        })
+        .await;
+        let rotation = CaRotation::new(
+            cert_manager.clone(),
+            store.clone(),
+            RotationConfig::default(),
+        );
+        let old = cert_manager
+            .issue_certificate(request("old.example.com"))
+            .await
+            .unwrap();
+        let previous_ca = cert_manager.ca();
+        let previous_key_id = previous_ca.key_id().unwrap();
+        rotation.stage(None, None, None).await.unwrap();
+        rotation.activate().await.unwrap();
+        let active_key_id = cert_manager.ca().key_id().unwrap();
+
+        // Each certificate points at the CRL of the CA that issued it
+        let crl_url = |pem: &str| {
+            let cert = X509::from_pem(pem.as_bytes()).unwrap();
+            let points = cert.crl_distribution_points().unwrap();
+            let name = points[0].distpoint().unwrap().fullname().unwrap();
+            name[0].uri().unwrap().to_string()
+        };
+        assert_eq!(
+            crl_url(&old.certificate_pem),
+            format!("http://ca.example.com/crl/{}", previous_key_id)
+        );
+        let new = cert_manager
+            .issue_certificate(request("new.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(
+            crl_url(&new.certificate_pem),
+            format!("http://ca.example.com/crl/{}", active_key_id)
+        );
+
+        cert_manager
+            .revoke_certificate(&old.certificate_id, Some("keyCompromise"))
+            .await
+            .unwrap();
+
+        let serials = |crl: &X509Crl| -> Vec<String> {
+            crl.get_revoked()
+                .into_iter()
+                .flatten()
+                .map(|revoked| {
+                    let serial = revoked.serial_number().to_bn().unwrap();
+                    serial.to_hex_str().unwrap().to_string()
+                })
+                .collect()
+        };
+        let crl = cert_manager
+            .current_crl(Some(&previous_key_id))
+            .await
+            .unwrap()
+            .unwrap();
+        let crl = X509Crl::from_der(&crl).unwrap();
+        assert!(crl.verify(&previous_ca.cert.public_key().unwrap()).unwrap());
+        assert_eq!(serials(&crl), [old.serial_number]);
+
+        let active_crl = cert_manager.current_crl(None).await.unwrap().unwrap();
+        let active_crl = X509Crl::from_der(&active_crl).unwrap();
+        assert!(active_crl
+            .verify(&cert_manager.ca().cert.public_key().unwrap())
+            .unwrap());
+        assert!(serials(&active_crl).is_empty());
+
+        // The previous CA's CRL goes once it is retired
+        rotation.retire(true).await.unwrap();
+        assert!(cert_manager
+            .current_crl(Some(&previous_key_id))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn refuses_to_stage_the_active_key() {
+        let (rotation, _store, _dir) = rotation().await;
+        let config = rotation.cert_manager.config();
+
+        let error = rotation
+            .stage(
+                Some(config.ca_cert_path.clone()),
+                Some(config.ca_key_path.clone()),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(error, CertAgentError::InvalidRequest(_)));
+        assert_eq!(rotation.status().await.unwrap().phase, "idle");
+    }
+
+    #[tokio::test]
+    async fn resumes_an_interrupted_activation_without_losing_the_previous_ca() {
+        let (rotation, store, _dir) = rotation().await;
+        let original = rotation.cert_manager.ca().chain_pem().unwrap();
+        rotation.stage(None, None, None).await.unwrap();
+        rotation.activate().await.unwrap();
+
+        // As if the agent crashed after writing the staged CA but before
+        // recording the activation
+        let mut record = store.get_ca_rotation().await.unwrap().unwrap();
+        record.phase = "staged".to_string();
+        store.store_ca_rotation(&record).await.unwrap();
+        assert!(rotation.retire(false).await.is_err());
+
+        let status = rotation.activate().await.unwrap();
+        assert_eq!(status.phase, "reissuing");
+        assert!(status.trust_bundle_pem.contains(&original[0]));
+
+        let previous_cert = previous_path(&rotation.cert_manager.config().ca_cert_path);
+        let previous_cert = fs::read_to_string(previous_cert).await.unwrap();
+        assert_eq!(previous_cert, original[0]);
+    }
+}
@@ -0,0 +1,1044 @@
+use crate::config::{Config, KeyAlgorithm, StorageBackend};
+use crate::error::{CertAgentError, Result};
+use crate::redis_client::RedisClient;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+mod memory;
+mod sql;
+
+pub use memory::MemoryStore;
+pub use sql::SqlStore;
+
+/// Batch size when listing without pagination, and for batched store
+/// commands.
+pub(crate) const LIST_BATCH_SIZE: usize = 500;
+
+/// Events published with `publish_event`, as `(event, data)` pairs.
+pub type EventStream = BoxStream<'static, (String, String)>;
+
+/// Certificate inventory storage: certificate records, CRL state, CA
+/// rotation state and certificate events, kept in Redis, a SQL database or
+/// memory depending on `storage.backend`. Implementations must keep each
+/// method atomic on its own; callers do not hold locks across calls.
+#[async_trait]
+pub trait CertificateStore: std::fmt::Debug + Send + Sync {
+    /// Stores a newly issued certificate and publishes its events, as one
+    /// transaction. A renewal, one with `predecessor_id` set, also marks the
+    /// predecessor superseded in the same transaction; when the predecessor
+    /// is no longer active nothing is written and `false` is returned.
+    async fn record_issuance(&self, cert_record: &CertificateRecord) -> Result<bool>;
+
+    async fn get_certificate(&self, certificate_id: &str) -> Result<Option<CertificateRecord>>;
+
+    async fn get_certificate_by_serial(
+        &self,
+        serial_number: &str,
+    ) -> Result<Option<CertificateRecord>>;
+
+    /// Marks a certificate revoked, recording when and why for CRL generation.
+    /// A certificate that is already revoked keeps its original date and
+    /// reason and `Some(false)` is returned; `None` if it does not exist.
+    async fn record_revocation(
+        &self,
+        certificate_id: &str,
+        revoked_at: i64,
+        reason: Option<&str>,
+    ) -> Result<Option<bool>>;
+
+    /// Marks an active certificate superseded by its renewal `successor_id`.
+    /// Returns `false` if the certificate does not exist or was revoked,
+    /// renewed or expired in the meantime.
+    async fn supersede(
+        &self,
+        certificate_id: &str,
+        successor_id: &str,
+        superseded_at: i64,
+    ) -> Result<bool>;
+
+    /// Up to `page_size` certificates matching `query` in its sort order,
+    /// starting after the cursor `page_token` returned with the previous page.
+    async fn list_certificates_page(
+        &self,
+        query: &CertificateQuery,
+        page_size: usize,
+        page_token: Option<&str>,
+    ) -> Result<CertificatePage>;
+
+    /// Every certificate, optionally with the given status, in issue order.
+    async fn list_certificates(
+        &self,
+        status_filter: Option<&str>,
+    ) -> Result<Vec<CertificateRecord>> {
+        let query = CertificateQuery {
+            status: status_filter.map(str::to_string),
+            ..Default::default()
+        };
+        self.collect_certificates(&query).await
+    }
+
+    /// Every certificate renewed from the first certificate `lineage_id`,
+    /// including it, oldest first.
+    async fn get_lineage(&self, lineage_id: &str) -> Result<Vec<CertificateRecord>> {
+        let query = CertificateQuery {
+            lineage_id: Some(lineage_id.to_string()),
+            ..Default::default()
+        };
+        self.collect_certificates(&query).await
+    }
+
+    /// Every page of `query`, collected.
+    async fn collect_certificates(
+        &self,
+        query: &CertificateQuery,
+    ) -> Result<Vec<CertificateRecord>> {
+        let mut certificates = Vec::new();
+        let mut page_token = None;
+
+        loop {
+            let page = self
+                .list_certificates_page(query, LIST_BATCH_SIZE, page_token.as_deref())
+                .await?;
+            certificates.extend(page.certificates);
+            match page.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => return Ok(certificates),
+            }
+        }
+    }
+
+    /// Active certificates expiring within `threshold_days`, soonest first.
+    async fn get_expiring_certificates(
+        &self,
+        threshold_days: u32,
+    ) -> Result<Vec<CertificateRecord>>;
+
+    /// Certificates still marked active although `expires_at` has passed.
+    async fn get_lapsed_certificates(&self) -> Result<Vec<CertificateRecord>>;
+
+    /// Marks an active certificate whose `expires_at` has passed as expired.
+    /// Returns `false` when the certificate was revoked, renewed or deleted
+    /// in the meantime.
+    async fn expire_certificate(&self, certificate_id: &str) -> Result<bool>;
+
+    async fn delete_certificate(&self, certificate_id: &str) -> Result<()>;
+
+    /// Periodic consistency repair: removes whatever deleted or expired
+    /// records left behind and reports records the store cannot find.
+    async fn repair(&self) -> Result<StoreRepair>;
+
+    // CRL storage
+    async fn next_crl_number(&self) -> Result<u64>;
+
+    /// Stores the current CRL of the CA with key identifier `issuer_key_id`;
+    /// each CA publishes its own CRL while a rotation is in progress.
+    async fn store_crl(&self, issuer_key_id: &str, crl_der: &[u8]) -> Result<()>;
+
+    async fn get_crl(&self, issuer_key_id: &str) -> Result<Option<Vec<u8>>>;
+
+    // CA rotation state
+    async fn get_ca_rotation(&self) -> Result<Option<CaRotationRecord>>;
+
+    async fn store_ca_rotation(&self, rotation: &CaRotationRecord) -> Result<()>;
+
+    async fn delete_ca_rotation(&self) -> Result<()>;
+
+    // Idempotency keys
+    /// Stores `record` under `key` for `ttl_seconds` unless the key is
+    /// already taken, in which case the record stored under it is returned
+    /// and nothing is written.
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        ttl_seconds: u64,
+    ) -> Result<Option<IdempotencyRecord>>;
+
+    /// Replaces the record under `key`, which then expires `ttl_seconds`
+    /// from now.
+    async fn store_idempotency_record(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        ttl_seconds: u64,
+    ) -> Result<()>;
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<()>;
+
+    // Real-time notifications
+    async fn publish_event(&self, event: &str, data: &str) -> Result<()>;
+
+    async fn subscribe_events(&self) -> Result<EventStream>;
+}
+
+/// Opens the storage backend selected by `config.storage`, bringing its
+/// schema or indexes up to date.
+pub async fn open(config: &Config) -> Result<Arc<dyn CertificateStore>> {
+    let retention_days = config.watcher.expired_retention_days;
+
+    match config.storage.backend {
+        StorageBackend::Redis => {
+            let redis = RedisClient::new(&config.redis.url, retention_days).await?;
+            info!("Connected to Redis at: {}", config.redis.url);
+            redis.ensure_indexes().await?;
+            Ok(Arc::new(redis))
+        }
+        StorageBackend::Sqlite | StorageBackend::Postgres => {
+            let url = config.storage.url.as_deref().ok_or_else(|| {
+                CertAgentError::Config(config::ConfigError::Message(
+                    "storage.url is required for SQL storage".to_string(),
+                ))
+            })?;
+            let store =
+                SqlStore::connect(url, config.storage.max_connections, retention_days).await?;
+            info!("Connected to {:?} storage", config.storage.backend);
+            Ok(Arc::new(store))
+        }
+        StorageBackend::Memory => {
+            warn!("Using in-memory storage; certificate records are lost on restart");
+            Ok(Arc::new(MemoryStore::new(retention_days)))
+        }
+    }
+}
+
+/// Outcome of `CertificateStore::repair`.
+#[derive(Debug, Clone, Default)]
+pub struct StoreRepair {
+    /// Dangling index entries, or records past their retention window in
+    /// stores without TTLs, that were removed
+    pub removed_entries: usize,
+    /// Records missing from the indexes, left in place for inspection
+    pub orphaned_records: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateRecord {
+    pub certificate_id: String,
+    pub common_name: String,
+    pub dns_names: Vec<String>,
+    pub ip_addresses: Vec<String>,
+    pub status: String,
+    pub expires_at: i64,
+    pub issued_at: i64,
+    pub metadata: HashMap<String, String>,
+    /// Signature algorithm of the issued certificate, e.g. `sha384WithRSAEncryption`
+    #[serde(default)]
+    pub signature_algorithm: String,
+    /// Certificate serial number, uppercase hex
+    #[serde(default)]
+    pub serial_number: String,
+    /// SHA-256 of the DER certificate, uppercase hex
+    #[serde(default)]
+    pub fingerprint_sha256: String,
+    /// Key identifier of the issuing CA, uppercase hex
+    #[serde(default)]
+    pub issuer_key_id: String,
+    /// Name of the certificate profile the certificate was issued under
+    #[serde(default)]
+    pub profile: String,
+    /// Caller identity that issued the certificate; empty when issued
+    /// without authentication
+    #[serde(default)]
+    pub owner: String,
+    /// Subject fields besides the common name, kept so renewal reproduces
+    /// the subject
+    #[serde(default)]
+    pub organization: Option<String>,
+    #[serde(default)]
+    pub organizational_unit: Option<String>,
+    #[serde(default)]
+    pub country: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub locality: Option<String>,
+    /// Type of the certificate's key; `None` in records written before the
+    /// subject and key type were kept
+    #[serde(default)]
+    pub key_algorithm: Option<KeyAlgorithm>,
+    /// The key pair was generated by the client, which sent a CSR; the agent
+    /// never held the private key, so only a new CSR can renew the certificate
+    #[serde(default)]
+    pub client_key: bool,
+    /// The agent's own gRPC serving certificate, issued by `grpc.tls.bootstrap`;
+    /// the agent issues a new one at startup rather than the watcher renewing it
+    #[serde(default)]
+    pub tls_bootstrap: bool,
+    /// ID of the first certificate in the renewal chain, a stable identity
+    /// across renewals; empty in records written before lineage was kept,
+    /// which are their own lineage
+    #[serde(default)]
+    pub lineage_id: String,
+    /// Certificate this one renewed
+    #[serde(default)]
+    pub predecessor_id: Option<String>,
+    /// Certificate that renewed this one
+    #[serde(default)]
+    pub successor_id: Option<String>,
+    /// When a renewal superseded the certificate
+    #[serde(default)]
+    pub superseded_at: Option<i64>,
+    #[serde(default)]
+    pub revoked_at: Option<i64>,
+    #[serde(default)]
+    pub revocation_reason: Option<String>,
+}
+
+impl CertificateRecord {
+    /// Lineage of the certificate, falling back to its own ID.
+    pub fn lineage(&self) -> &str {
+        if self.lineage_id.is_empty() {
+            &self.certificate_id
+        } else {
+            &self.lineage_id
+        }
+    }
+
+    /// Whether the certificate is due for renewal at `now`: within
+    /// `threshold_days` of expiry, or the last third of its lifetime when
+    /// that is shorter, so short-lived certificates are not renewed as soon
+    /// as they are issued.
+    pub fn renewal_due(&self, threshold_days: u32, now: i64) -> bool {
+        let lifetime = self.expires_at - self.issued_at;
+        let renew_before = (threshold_days as i64 * 24 * 60 * 60).min(lifetime / 3);
+        self.expires_at - now <= renew_before
+    }
+
+    /// A superseded certificate as the CRL and OCSP list it once the renewal
+    /// overlap of `overlap_seconds` has ended at `now`: revoked as superseded
+    /// when the overlap ended. `None` during the overlap and for other
+    /// statuses.
+    pub fn retired(&self, overlap_seconds: u64, now: i64) -> Option<CertificateRecord> {
+        let retired_at = self.superseded_at? + overlap_seconds as i64;
+        if self.status != "superseded" || retired_at > now {
+            return None;
+        }
+
+        let mut retired = self.clone();
+        retired.status = "revoked".to_string();
+        retired.revoked_at = Some(retired_at);
+        retired.revocation_reason = Some("superseded".to_string());
+        Some(retired)
+    }
+}
+
+/// Events published for a newly issued certificate: `issued`, and for a
+/// renewal `superseded` for the predecessor and `renewed`.
+pub(crate) fn issuance_events(cert_record: &CertificateRecord) -> Vec<(&'static str, &str)> {
+    let certificate_id = cert_record.certificate_id.as_str();
+    let mut events = vec![("issued", certificate_id)];
+    if let Some(predecessor_id) = &cert_record.predecessor_id {
+        events.push(("superseded", predecessor_id));
+        events.push(("renewed", certificate_id));
+    }
+    events
+}
+
+/// Marks `cert_record` revoked unless it already is, so the date and reason
+/// published in the CRL never change.
+pub(crate) fn mark_revoked(
+    cert_record: &mut CertificateRecord,
+    revoked_at: i64,
+    reason: Option<&str>,
+) -> bool {
+    if cert_record.status == "revoked" {
+        return false;
+    }
+    cert_record.status = "revoked".to_string();
+    cert_record.revoked_at = Some(revoked_at);
+    cert_record.revocation_reason = reason.map(str::to_string);
+    true
+}
+
+/// Marks `cert_record` superseded by `successor_id` if it is still active.
+pub(crate) fn mark_superseded(
+    cert_record: &mut CertificateRecord,
+    successor_id: &str,
+    superseded_at: i64,
+) -> bool {
+    if cert_record.status != "active" {
+        return false;
+    }
+    cert_record.status = "superseded".to_string();
+    cert_record.successor_id = Some(successor_id.to_string());
+    cert_record.superseded_at = Some(superseded_at);
+    true
+}
+
+/// Current certificate of a lineage listed oldest first: the newest active
+/// certificate that has not been renewed.
+pub fn current_certificate(lineage: &[CertificateRecord]) -> Option<&CertificateRecord> {
+    lineage
+        .iter()
+        .rev()
+        .find(|cert_record| cert_record.status == "active" && cert_record.successor_id.is_none())
+}
+
+/// One page of a certificate listing.
+#[derive(Debug, Clone)]
+pub struct CertificatePage {
+    pub certificates: Vec<CertificateRecord>,
+    /// Opaque cursor for the next page; `None` on the last page
+    pub next_page_token: Option<String>,
+}
+
+/// Filters and sort order for a certificate listing. Every filter that is
+/// set must match; time bounds are Unix seconds, `*_after` inclusive and
+/// `*_before` exclusive.
+#[derive(Debug, Clone, Default)]
+pub struct CertificateQuery {
+    pub status: Option<String>,
+    /// Exact match, case-insensitive
+    pub common_name: Option<String>,
+    /// Case-insensitive
+    pub common_name_prefix: Option<String>,
+    pub dns_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub metadata: HashMap<String, String>,
+    pub issuer_key_id: Option<String>,
+    pub profile: Option<String>,
+    pub lineage_id: Option<String>,
+    pub issued_after: Option<i64>,
+    pub issued_before: Option<i64>,
+    pub expires_after: Option<i64>,
+    pub expires_before: Option<i64>,
+    pub sort: ListSort,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListSort {
+    #[default]
+    IssuedAsc,
+    IssuedDesc,
+    ExpiresAsc,
+    ExpiresDesc,
+}
+
+impl ListSort {
+    /// Ties page tokens to the order they were issued for.
+    pub(crate) fn token_prefix(self) -> &'static str {
+        match self {
+            ListSort::IssuedAsc => "ia",
+            ListSort::IssuedDesc => "id",
+            ListSort::ExpiresAsc => "ea",
+            ListSort::ExpiresDesc => "ed",
+        }
+    }
+}
+
+impl CertificateQuery {
+    /// Checks every filter against the record itself, which also guards
+    /// against index entries that went stale.
+    pub(crate) fn matches(&self, cert_record: &CertificateRecord) -> bool {
+        let within = |time: i64, after: Option<i64>, before: Option<i64>| {
+            after.is_none_or(|after| time >= after) && before.is_none_or(|before| time < before)
+        };
+
+        self.status
+            .as_ref()
+            .is_none_or(|status| cert_record.status == *status)
+            && self
+                .common_name
+                .as_ref()
+                .is_none_or(|name| cert_record.common_name.to_lowercase() == name.to_lowercase())
+            && self.common_name_prefix.as_ref().is_none_or(|prefix| {
+                cert_record
+                    .common_name
+                    .to_lowercase()
+                    .starts_with(&prefix.to_lowercase())
+            })
+            && self.dns_name.as_ref().is_none_or(|dns_name| {
+                cert_record
+                    .dns_names
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(dns_name))
+            })
+            && self.ip_address.as_ref().is_none_or(|ip_address| {
+                cert_record
+                    .ip_addresses
+                    .iter()
+                    .any(|ip| canonical_ip(ip) == canonical_ip(ip_address))
+            })
+            && self
+                .metadata
+                .iter()
+                .all(|(key, value)| cert_record.metadata.get(key) == Some(value))
+            && self
+                .issuer_key_id
+                .as_ref()
+                .is_none_or(|key_id| cert_record.issuer_key_id.eq_ignore_ascii_case(key_id))
+            && self
+                .profile
+                .as_ref()
+                .is_none_or(|profile| cert_record.profile == *profile)
+            && self
+                .lineage_id
+                .as_ref()
+                .is_none_or(|lineage_id| cert_record.lineage() == lineage_id)
+            && within(cert_record.issued_at, self.issued_after, self.issued_before)
+            && within(
+                cert_record.expires_at,
+                self.expires_after,
+                self.expires_before,
+            )
+    }
+}
+
+/// In-progress CA rotation; absent when no rotation is under way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaRotationRecord {
+    /// "staged" or "reissuing"
+    pub phase: String,
+    /// Staged CA files, copied over the configured CA paths on activation
+    pub staged_cert_path: String,
+    pub staged_key_path: String,
+    pub staged_chain_path: Option<String>,
+    /// PEM chain of the staged CA, issuing CA first
+    pub staged_ca_pem: Vec<String>,
+    /// PEM chain of the CA being replaced, set on activation before any CA
+    /// file is written; set while still staged if activation was interrupted
+    #[serde(default)]
+    pub previous_ca_pem: Vec<String>,
+    pub staged_at: i64,
+    #[serde(default)]
+    pub activated_at: Option<i64>,
+    /// Time by which the watcher should have re-issued everything
+    #[serde(default)]
+    pub reissue_deadline: Option<i64>,
+}
+
+/// A request made under an idempotency key. Retries carrying the same key
+/// are answered from `response` instead of being carried out again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    /// Hash of the parameters of the request that first used the key
+    pub fingerprint: String,
+    /// Base64 of the encoded response; unset while that request is running
+    #[serde(default)]
+    pub response: Option<String>,
+}
+
+/// Canonical form of an IP address, so "::0001" and "::1" compare equal.
+pub(crate) fn canonical_ip(ip_address: &str) -> String {
+    match ip_address.parse::<IpAddr>() {
+        Ok(ip) => ip.to_string(),
+        Err(_) => ip_address.to_string(),
+    }
+}
+
+/// Opaque page token for a listing cursor: the last `{issued_at:020}:{id}`
+/// member returned and, when sorting by expiry, its expiry time.
+pub(crate) fn encode_page_token(sort: ListSort, (member, score): &(String, i64)) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{}:{}:{}", sort.token_prefix(), score, member))
+}
+
+/// Splits a page token member into the issue time and certificate id.
+pub(crate) fn split_page_member(member: &str) -> Result<(i64, &str)> {
+    member
+        .split_once(':')
+        .and_then(|(issued_at, id)| Some((issued_at.parse().ok()?, id)))
+        .ok_or_else(|| CertAgentError::InvalidRequest("Invalid page token".to_string()))
+}
+
+pub(crate) fn decode_page_token(token: &str, sort: ListSort) -> Result<(String, i64)> {
+    let invalid = || CertAgentError::InvalidRequest("Invalid page token".to_string());
+    let token = URL_SAFE_NO_PAD
+        .decode(token)
+        .ok()
+        .and_then(|token| String::from_utf8(token).ok())
+        .ok_or_else(invalid)?;
+
+    let mut parts = token.splitn(3, ':');
+    let (Some(prefix), Some(score), Some(member)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    if prefix != sort.token_prefix() {
+        return Err(CertAgentError::InvalidRequest(
+            "Page token belongs to a different sort order".to_string(),
+        ));
+    }
+    if !member.contains(':') {
+        return Err(invalid());
+    }
+
+    Ok((member.to_string(), score.parse().map_err(|_| invalid())?))
+}
+
+/// Events from an in-process channel; a slow subscriber misses events
+/// rather than stalling the others.
+pub(crate) fn broadcast_events(receiver: broadcast::Receiver<(String, String)>) -> EventStream {
+    Box::pin(futures::stream::unfold(
+        receiver,
+        |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    /// Every backend that runs in process, each with its own empty store.
+    async fn stores(retention_days: u32) -> Vec<(&'static str, Arc<dyn CertificateStore>)> {
+        // A named in-memory database, shared by the pool's connections
+        let url = format!(
+            "sqlite:file:{}?mode=memory&cache=shared",
+            uuid::Uuid::new_v4().simple()
+        );
+        let sqlite = SqlStore::connect(&url, 4, retention_days).await.unwrap();
+        vec![
+            ("memory", Arc::new(MemoryStore::new(retention_days))),
+            ("sqlite", Arc::new(sqlite)),
+        ]
+    }
+
+    async fn stores_with(
+        records: &[CertificateRecord],
+    ) -> Vec<(&'static str, Arc<dyn CertificateStore>)> {
+        let stores = stores(90).await;
+        for (_, store) in &stores {
+            for cert_record in records {
+                store.record_issuance(cert_record).await.unwrap();
+            }
+        }
+        stores
+    }
+
+    fn record(id: &str, issued_at: i64, expires_at: i64) -> CertificateRecord {
+        CertificateRecord {
+            certificate_id: id.to_string(),
+            common_name: format!("{}.example.com", id),
+            dns_names: vec![format!("{}.example.com", id)],
+            ip_addresses: vec!["10.0.0.1".to_string()],
+            status: "active".to_string(),
+            expires_at,
+            issued_at,
+            metadata: HashMap::from([("team".to_string(), "payments".to_string())]),
+            signature_algorithm: "ecdsa-with-SHA256".to_string(),
+            serial_number: format!("{}00", id.to_uppercase()),
+            fingerprint_sha256: String::new(),
+            issuer_key_id: "AB12".to_string(),
+            profile: "default".to_string(),
+            owner: String::new(),
+            organization: None,
+            organizational_unit: None,
+            country: None,
+            state: None,
+            locality: None,
+            key_algorithm: None,
+            client_key: false,
+            tls_bootstrap: false,
+            lineage_id: String::new(),
+            predecessor_id: None,
+            successor_id: None,
+            superseded_at: None,
+            revoked_at: None,
+            revocation_reason: None,
+        }
+    }
+
+    async fn collect_ids(
+        store: &dyn CertificateStore,
+        query: &CertificateQuery,
+        page_size: usize,
+    ) -> Vec<String> {
+        let mut ids = Vec::new();
+        let mut page_token = None;
+        loop {
+            let page = store
+                .list_certificates_page(query, page_size, page_token.as_deref())
+                .await
+                .unwrap();
+            assert!(page.certificates.len() <= page_size);
+            ids.extend(page.certificates.into_iter().map(|c| c.certificate_id));
+            match page.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => return ids,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_through_every_sort_order() {
+        let now = chrono::Utc::now().timestamp();
+        for (backend, store) in stores_with(&[
+            record("a", 100, now + 300),
+            record("b", 100, now + 100),
+            record("c", 200, now + 200),
+            record("d", 300, now + 100),
+        ])
+        .await
+        {
+            for (sort, expected) in [
+                (ListSort::IssuedAsc, ["a", "b", "c", "d"]),
+                (ListSort::IssuedDesc, ["d", "c", "b", "a"]),
+                (ListSort::ExpiresAsc, ["b", "d", "c", "a"]),
+                (ListSort::ExpiresDesc, ["a", "c", "d", "b"]),
+            ] {
+                let query = CertificateQuery {
+                    sort,
+                    ..Default::default()
+                };
+                for page_size in [3, 1] {
+                    assert_eq!(
+                        collect_ids(store.as_ref(), &query, page_size).await,
+                        expected,
+                        "{} {:?}",
+                        backend,
+                        sort
+                    );
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn filters_listings() {
+        let now = chrono::Utc::now().timestamp();
+        let mut revoked = record("b", 200, now + 100);
+        revoked.status = "revoked".to_string();
+
+        for (backend, store) in stores_with(&[record("a", 100, now + 100), revoked]).await {
+            let query = CertificateQuery {
+                status: Some("active".to_string()),
+                ..Default::default()
+            };
+            assert_eq!(
+                collect_ids(store.as_ref(), &query, 10).await,
+                ["a"],
+                "{}",
+                backend
+            );
+
+            let query = CertificateQuery {
+                dns_name: Some("B.EXAMPLE.COM".to_string()),
+                ip_address: Some("10.0.0.1".to_string()),
+                ..Default::default()
+            };
+            assert_eq!(
+                collect_ids(store.as_ref(), &query, 10).await,
+                ["b"],
+                "{}",
+                backend
+            );
+
+            let query = CertificateQuery {
+                metadata: HashMap::from([("team".to_string(), "search".to_string())]),
+                ..Default::default()
+            };
+            assert!(
+                collect_ids(store.as_ref(), &query, 10).await.is_empty(),
+                "{}",
+                backend
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_page_tokens_of_another_sort_order() {
+        for (backend, store) in stores_with(&[record("a", 100, 1000), record("b", 200, 1000)]).await
+        {
+            let page = store
+                .list_certificates_page(&CertificateQuery::default(), 1, None)
+                .await
+                .unwrap();
+
+            let query = CertificateQuery {
+                sort: ListSort::ExpiresAsc,
+                ..Default::default()
+            };
+            let result = store
+                .list_certificates_page(&query, 1, page.next_page_token.as_deref())
+                .await;
+            assert!(result.is_err(), "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn expires_only_lapsed_active_certificates() {
+        let now = chrono::Utc::now().timestamp();
+        let mut revoked = record("revoked", 100, now - 10);
+        revoked.status = "revoked".to_string();
+
+        for (backend, store) in stores_with(&[
+            record("lapsed", 100, now - 10),
+            record("current", 100, now + 10),
+            revoked.clone(),
+        ])
+        .await
+        {
+            let lapsed = store.get_lapsed_certificates().await.unwrap();
+            assert_eq!(lapsed.len(), 1, "{}", backend);
+            assert_eq!(lapsed[0].certificate_id, "lapsed");
+
+            assert!(store.expire_certificate("lapsed").await.unwrap());
+            assert!(!store.expire_certificate("lapsed").await.unwrap());
+            assert!(!store.expire_certificate("current").await.unwrap());
+            assert!(!store.expire_certificate("revoked").await.unwrap());
+            assert!(!store.expire_certificate("missing").await.unwrap());
+            let expired = store.get_certificate("lapsed").await.unwrap().unwrap();
+            assert_eq!(expired.status, "expired", "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_expiring_certificates_soonest_first() {
+        let now = chrono::Utc::now().timestamp();
+        let day = 24 * 60 * 60;
+
+        for (backend, store) in stores_with(&[
+            record("later", 100, now + 20 * day),
+            record("sooner", 100, now + 2 * day),
+            record("distant", 100, now + 60 * day),
+            record("lapsed", 100, now - day),
+        ])
+        .await
+        {
+            let expiring = store.get_expiring_certificates(30).await.unwrap();
+            let ids: Vec<_> = expiring.iter().map(|c| c.certificate_id.as_str()).collect();
+            assert_eq!(ids, ["sooner", "later"], "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn repair_deletes_records_past_retention() {
+        let now = chrono::Utc::now().timestamp();
+
+        for (backend, store) in stores_with(&[
+            record("old", 100, now - 91 * 24 * 60 * 60),
+            record("recent", 100, now - 24 * 60 * 60),
+        ])
+        .await
+        {
+            assert_eq!(
+                store.repair().await.unwrap().removed_entries,
+                1,
+                "{}",
+                backend
+            );
+            assert!(store.get_certificate("old").await.unwrap().is_none());
+            assert!(store.get_certificate("recent").await.unwrap().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn records_renewals_in_one_transaction() {
+        let now = chrono::Utc::now().timestamp();
+
+        for (backend, store) in stores_with(&[record("a", 100, now + 100)]).await {
+            let mut events = store.subscribe_events().await.unwrap();
+            let mut renewal = record("b", 200, now + 200);
+            renewal.lineage_id = "a".to_string();
+            renewal.predecessor_id = Some("a".to_string());
+
+            assert!(
+                store.record_issuance(&renewal).await.unwrap(),
+                "{}",
+                backend
+            );
+            let predecessor = store.get_certificate("a").await.unwrap().unwrap();
+            assert_eq!(predecessor.status, "superseded", "{}", backend);
+            assert_eq!(predecessor.successor_id.as_deref(), Some("b"));
+            assert_eq!(predecessor.superseded_at, Some(200));
+            let published: Vec<_> = (&mut events)
+                .take(3)
+                .map(|(event, _)| event)
+                .collect()
+                .await;
+            assert_eq!(
+                published,
+                ["issued", "superseded", "renewed"],
+                "{}",
+                backend
+            );
+
+            // The predecessor was renewed already: nothing is written
+            let mut second = record("c", 300, now + 300);
+            second.predecessor_id = Some("a".to_string());
+            assert!(
+                !store.record_issuance(&second).await.unwrap(),
+                "{}",
+                backend
+            );
+            assert!(store.get_certificate("c").await.unwrap().is_none());
+            let predecessor = store.get_certificate("a").await.unwrap().unwrap();
+            assert_eq!(
+                predecessor.successor_id.as_deref(),
+                Some("b"),
+                "{}",
+                backend
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn supersedes_only_active_certificates() {
+        let now = chrono::Utc::now().timestamp();
+        let mut revoked = record("revoked", 100, now + 100);
+        revoked.status = "revoked".to_string();
+
+        for (backend, store) in
+            stores_with(&[record("active", 100, now + 100), revoked.clone()]).await
+        {
+            assert!(
+                store.supersede("active", "next", now).await.unwrap(),
+                "{}",
+                backend
+            );
+            assert!(!store.supersede("active", "other", now).await.unwrap());
+            assert!(!store.supersede("revoked", "next", now).await.unwrap());
+            assert!(!store.supersede("missing", "next", now).await.unwrap());
+
+            let superseded = store.get_certificate("active").await.unwrap().unwrap();
+            assert_eq!(
+                superseded.successor_id.as_deref(),
+                Some("next"),
+                "{}",
+                backend
+            );
+            let revoked = store.get_certificate("revoked").await.unwrap().unwrap();
+            assert_eq!(revoked.status, "revoked", "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn revokes_only_once() {
+        let now = chrono::Utc::now().timestamp();
+
+        for (backend, store) in stores_with(&[record("a", 100, now + 100)]).await {
+            let revoked = store.record_revocation("a", 500, Some("keyCompromise"));
+            assert_eq!(revoked.await.unwrap(), Some(true), "{}", backend);
+            let again = store.record_revocation("a", 600, Some("superseded"));
+            assert_eq!(again.await.unwrap(), Some(false), "{}", backend);
+            let missing = store.record_revocation("missing", 600, None);
+            assert_eq!(missing.await.unwrap(), None, "{}", backend);
+
+            let cert_record = store.get_certificate("a").await.unwrap().unwrap();
+            assert_eq!(cert_record.revoked_at, Some(500), "{}", backend);
+            assert_eq!(
+                cert_record.revocation_reason.as_deref(),
+                Some("keyCompromise"),
+                "{}",
+                backend
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn claims_idempotency_keys_once() {
+        let pending = IdempotencyRecord {
+            fingerprint: "request".to_string(),
+            response: None,
+        };
+        let completed = IdempotencyRecord {
+            response: Some("response".to_string()),
+            ..pending.clone()
+        };
+
+        for (backend, store) in stores(0).await {
+            assert_eq!(
+                store
+                    .claim_idempotency_key("k", &pending, 60)
+                    .await
+                    .unwrap(),
+                None
+            );
+            assert_eq!(
+                store
+                    .claim_idempotency_key("k", &completed, 60)
+                    .await
+                    .unwrap(),
+                Some(pending.clone()),
+                "{}",
+                backend
+            );
+
+            store
+                .store_idempotency_record("k", &completed, 60)
+                .await
+                .unwrap();
+            assert_eq!(
+                store
+                    .claim_idempotency_key("k", &pending, 60)
+                    .await
+                    .unwrap(),
+                Some(completed.clone()),
+                "{}",
+                backend
+            );
+
+            store.release_idempotency_key("k").await.unwrap();
+            assert_eq!(
+                store
+                    .claim_idempotency_key("k", &pending, 60)
+                    .await
+                    .unwrap(),
+                None,
+                "{}",
+                backend
+            );
+
+            // Expired keys are free again
+            store
+                .store_idempotency_record("gone", &completed, 0)
+                .await
+                .unwrap();
+            assert_eq!(
+                store
+                    .claim_idempotency_key("gone", &pending, 60)
+                    .await
+                    .unwrap(),
+                None,
+                "{}",
+                backend
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_events_to_every_subscriber() {
+        for (backend, store) in stores(0).await {
+            // Published before anyone listens, so nobody sees it
+            store.publish_event("issued", "early").await.unwrap();
+
+            let mut first = store.subscribe_events().await.unwrap();
+            let mut second = store.subscribe_events().await.unwrap();
+            store
+                .publish_event("revoked", "a:keyCompromise")
+                .await
+                .unwrap();
+
+            let expected = ("revoked".to_string(), "a:keyCompromise".to_string());
+            assert_eq!(first.next().await, Some(expected.clone()), "{}", backend);
+            assert_eq!(second.next().await, Some(expected), "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn numbers_crls_sequentially() {
+        for (backend, store) in stores(0).await {
+            assert_eq!(store.next_crl_number().await.unwrap(), 1, "{}", backend);
+            assert_eq!(store.next_crl_number().await.unwrap(), 2, "{}", backend);
+            assert!(store.get_crl("AA").await.unwrap().is_none());
+            store.store_crl("AA", b"crl").await.unwrap();
+            store.store_crl("BB", b"other").await.unwrap();
+            assert_eq!(store.get_crl("AA").await.unwrap().unwrap(), b"crl");
+        }
+    }
+}
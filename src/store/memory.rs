@@ -0,0 +1,373 @@
+use crate::error::Result;
+use crate::store::{
+    broadcast_events, decode_page_token, encode_page_token, issuance_events, mark_revoked,
+    mark_superseded, split_page_member, CaRotationRecord, CertificatePage, CertificateQuery,
+    CertificateRecord, CertificateStore, EventStream, IdempotencyRecord, ListSort, StoreRepair,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Events buffered for each watch stream before the slowest ones miss some.
+const EVENT_CAPACITY: usize = 1024;
+
+/// Certificate store kept in process memory, for tests and single-node
+/// development. Everything is lost when the process exits.
+#[derive(Debug)]
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+    /// How long records are kept past `expires_at`; `None` keeps them forever
+    retention_seconds: Option<i64>,
+    events: broadcast::Sender<(String, String)>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    certificates: HashMap<String, CertificateRecord>,
+    crl_number: u64,
+    /// Current CRL of each CA by key identifier
+    crls: HashMap<String, Vec<u8>>,
+    ca_rotation: Option<CaRotationRecord>,
+    /// Idempotency records with the time they expire
+    idempotency: HashMap<String, (IdempotencyRecord, i64)>,
+}
+
+impl MemoryStore {
+    /// Records are deleted `retention_days` after the certificate expires;
+    /// 0 keeps them forever.
+    pub fn new(retention_days: u32) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            state: Mutex::new(MemoryState::default()),
+            retention_seconds: Some(retention_days as i64 * 24 * 60 * 60)
+                .filter(|seconds| *seconds > 0),
+            events,
+        }
+    }
+
+    /// Inserts or replaces a certificate record as is, for tests to set up
+    /// state the agent would not write itself.
+    #[cfg(test)]
+    pub(crate) fn put_certificate(&self, cert_record: &CertificateRecord) {
+        self.state
+            .lock()
+            .unwrap()
+            .certificates
+            .insert(cert_record.certificate_id.clone(), cert_record.clone());
+    }
+
+    /// Active certificates with `after < expires_at <= until`, soonest first.
+    fn active_expiring_between(&self, after: Option<i64>, until: i64) -> Vec<CertificateRecord> {
+        let state = self.state.lock().unwrap();
+        let mut certificates: Vec<_> = state
+            .certificates
+            .values()
+            .filter(|cert_record| {
+                cert_record.status == "active"
+                    && after.is_none_or(|after| cert_record.expires_at > after)
+                    && cert_record.expires_at <= until
+            })
+            .cloned()
+            .collect();
+        certificates.sort_by(|a, b| {
+            (a.expires_at, a.issued_at, &a.certificate_id).cmp(&(
+                b.expires_at,
+                b.issued_at,
+                &b.certificate_id,
+            ))
+        });
+        certificates
+    }
+}
+
+#[async_trait]
+impl CertificateStore for MemoryStore {
+    async fn record_issuance(&self, cert_record: &CertificateRecord) -> Result<bool> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(predecessor_id) = &cert_record.predecessor_id {
+                let superseded =
+                    state
+                        .certificates
+                        .get_mut(predecessor_id)
+                        .is_some_and(|predecessor| {
+                            mark_superseded(
+                                predecessor,
+                                &cert_record.certificate_id,
+                                cert_record.issued_at,
+                            )
+                        });
+                if !superseded {
+                    return Ok(false);
+                }
+            }
+            state
+                .certificates
+                .insert(cert_record.certificate_id.clone(), cert_record.clone());
+        }
+
+        for (event, data) in issuance_events(cert_record) {
+            self.publish_event(event, data).await?;
+        }
+        Ok(true)
+    }
+
+    async fn get_certificate(&self, certificate_id: &str) -> Result<Option<CertificateRecord>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .certificates
+            .get(certificate_id)
+            .cloned())
+    }
+
+    async fn get_certificate_by_serial(
+        &self,
+        serial_number: &str,
+    ) -> Result<Option<CertificateRecord>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .certificates
+            .values()
+            .find(|cert_record| cert_record.serial_number == serial_number)
+            .cloned())
+    }
+
+    async fn record_revocation(
+        &self,
+        certificate_id: &str,
+        revoked_at: i64,
+        reason: Option<&str>,
+    ) -> Result<Option<bool>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .certificates
+            .get_mut(certificate_id)
+            .map(|cert_record| mark_revoked(cert_record, revoked_at, reason)))
+    }
+
+    async fn supersede(
+        &self,
+        certificate_id: &str,
+        successor_id: &str,
+        superseded_at: i64,
+    ) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state
+            .certificates
+            .get_mut(certificate_id)
+            .is_some_and(|cert_record| mark_superseded(cert_record, successor_id, superseded_at)))
+    }
+
+    /// Filters and sorts a snapshot of every record; page tokens have the
+    /// same shape as the other stores'.
+    async fn list_certificates_page(
+        &self,
+        query: &CertificateQuery,
+        page_size: usize,
+        page_token: Option<&str>,
+    ) -> Result<CertificatePage> {
+        let by_expiry = matches!(query.sort, ListSort::ExpiresAsc | ListSort::ExpiresDesc);
+        let descending = matches!(query.sort, ListSort::IssuedDesc | ListSort::ExpiresDesc);
+        let sort_key = |cert_record: &CertificateRecord| {
+            (
+                if by_expiry { cert_record.expires_at } else { 0 },
+                cert_record.issued_at.max(0),
+                cert_record.certificate_id.clone(),
+            )
+        };
+
+        let cursor = match page_token {
+            Some(token) => {
+                let (member, score) = decode_page_token(token, query.sort)?;
+                let (issued_at, certificate_id) = split_page_member(&member)?;
+                Some((score, issued_at, certificate_id.to_string()))
+            }
+            None => None,
+        };
+
+        let mut certificates: Vec<_> = {
+            let state = self.state.lock().unwrap();
+            state
+                .certificates
+                .values()
+                .filter(|cert_record| query.matches(cert_record))
+                .filter(|cert_record| {
+                    cursor.as_ref().is_none_or(|cursor| {
+                        let key = sort_key(cert_record);
+                        if descending {
+                            key < *cursor
+                        } else {
+                            key > *cursor
+                        }
+                    })
+                })
+                .cloned()
+                .collect()
+        };
+        certificates.sort_by_key(sort_key);
+        if descending {
+            certificates.reverse();
+        }
+
+        let next_page_token = if certificates.len() > page_size {
+            certificates.truncate(page_size);
+            certificates.last().map(|last| {
+                let (score, issued_at, certificate_id) = sort_key(last);
+                let member = format!("{:020}:{}", issued_at, certificate_id);
+                encode_page_token(query.sort, &(member, score))
+            })
+        } else {
+            None
+        };
+
+        Ok(CertificatePage {
+            certificates,
+            next_page_token,
+        })
+    }
+
+    async fn get_expiring_certificates(
+        &self,
+        threshold_days: u32,
+    ) -> Result<Vec<CertificateRecord>> {
+        let current_time = chrono::Utc::now().timestamp();
+        let threshold_seconds = (threshold_days as i64) * 24 * 60 * 60;
+        Ok(self.active_expiring_between(Some(current_time), current_time + threshold_seconds))
+    }
+
+    async fn get_lapsed_certificates(&self) -> Result<Vec<CertificateRecord>> {
+        Ok(self.active_expiring_between(None, chrono::Utc::now().timestamp()))
+    }
+
+    async fn expire_certificate(&self, certificate_id: &str) -> Result<bool> {
+        let current_time = chrono::Utc::now().timestamp();
+        let mut state = self.state.lock().unwrap();
+
+        match state.certificates.get_mut(certificate_id) {
+            Some(cert_record)
+                if cert_record.status == "active" && cert_record.expires_at <= current_time =>
+            {
+                cert_record.status = "expired".to_string();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_certificate(&self, certificate_id: &str) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .certificates
+            .remove(certificate_id);
+        Ok(())
+    }
+
+    /// Deletes records past their retention window, as TTLs do in Redis.
+    async fn repair(&self) -> Result<StoreRepair> {
+        let mut repair = StoreRepair::default();
+
+        if let Some(retention) = self.retention_seconds {
+            let cutoff = chrono::Utc::now().timestamp() - retention;
+            let mut state = self.state.lock().unwrap();
+            let before = state.certificates.len();
+            state
+                .certificates
+                .retain(|_, cert_record| cert_record.expires_at >= cutoff);
+            repair.removed_entries = before - state.certificates.len();
+        }
+
+        Ok(repair)
+    }
+
+    async fn next_crl_number(&self) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        state.crl_number += 1;
+        Ok(state.crl_number)
+    }
+
+    async fn store_crl(&self, issuer_key_id: &str, crl_der: &[u8]) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .crls
+            .insert(issuer_key_id.to_string(), crl_der.to_vec());
+        Ok(())
+    }
+
+    async fn get_crl(&self, issuer_key_id: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.state.lock().unwrap().crls.get(issuer_key_id).cloned())
+    }
+
+    async fn get_ca_rotation(&self) -> Result<Option<CaRotationRecord>> {
+        Ok(self.state.lock().unwrap().ca_rotation.clone())
+    }
+
+    async fn store_ca_rotation(&self, rotation: &CaRotationRecord) -> Result<()> {
+        self.state.lock().unwrap().ca_rotation = Some(rotation.clone());
+        Ok(())
+    }
+
+    async fn delete_ca_rotation(&self) -> Result<()> {
+        self.state.lock().unwrap().ca_rotation = None;
+        Ok(())
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        ttl_seconds: u64,
+    ) -> Result<Option<IdempotencyRecord>> {
+        let now = chrono::Utc::now().timestamp();
+        let mut state = self.state.lock().unwrap();
+        state
+            .idempotency
+            .retain(|_, (_, expires_at)| *expires_at > now);
+
+        if let Some((existing, _)) = state.idempotency.get(key) {
+            return Ok(Some(existing.clone()));
+        }
+        state
+            .idempotency
+            .insert(key.to_string(), (record.clone(), now + ttl_seconds as i64));
+        Ok(None)
+    }
+
+    async fn store_idempotency_record(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        ttl_seconds: u64,
+    ) -> Result<()> {
+        let expires_at = chrono::Utc::now().timestamp() + ttl_seconds as i64;
+        self.state
+            .lock()
+            .unwrap()
+            .idempotency
+            .insert(key.to_string(), (record.clone(), expires_at));
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<()> {
+        self.state.lock().unwrap().idempotency.remove(key);
+        Ok(())
+    }
+
+    async fn publish_event(&self, event: &str, data: &str) -> Result<()> {
+        // No subscribers is not an error
+        let _ = self.events.send((event.to_string(), data.to_string()));
+        Ok(())
+    }
+
+    async fn subscribe_events(&self) -> Result<EventStream> {
+        Ok(broadcast_events(self.events.subscribe()))
+    }
+}
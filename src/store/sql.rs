@@ -0,0 +1,713 @@
+use crate::error::{CertAgentError, Result};
+use crate::store::{
+    broadcast_events, canonical_ip, decode_page_token, encode_page_token, issuance_events,
+    mark_revoked, mark_superseded, split_page_member, CaRotationRecord, CertificatePage,
+    CertificateQuery, CertificateRecord, CertificateStore, EventStream, IdempotencyRecord,
+    ListSort, StoreRepair,
+};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use sqlx::any::{AnyArguments, AnyPoolOptions};
+use sqlx::migrate::Migrator;
+use sqlx::query::Query;
+use sqlx::{Any, AnyPool, Row};
+use std::fmt::Write as _;
+use tokio::sync::broadcast;
+
+/// Schema migrations, shared by SQLite and PostgreSQL.
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Events buffered for each watch stream before the slowest ones miss some.
+const EVENT_CAPACITY: usize = 1024;
+
+/// Certificate store in SQLite or PostgreSQL, chosen by the URL scheme.
+/// Queries use `$N` placeholders, which both databases accept.
+///
+/// Events are delivered within this process only, so watch streams see the
+/// events of the agent instance they are connected to.
+#[derive(Debug, Clone)]
+pub struct SqlStore {
+    pool: AnyPool,
+    /// How long records are kept past `expires_at`; `None` keeps them forever
+    retention_seconds: Option<i64>,
+    events: broadcast::Sender<(String, String)>,
+}
+
+/// A value bound to a `$N` placeholder.
+enum Value {
+    Text(String),
+    Int(i64),
+}
+
+/// SQL text built up with numbered placeholders and the values bound to them.
+struct Statement {
+    sql: String,
+    values: Vec<Value>,
+}
+
+impl Statement {
+    fn new(sql: &str) -> Self {
+        Self {
+            sql: sql.to_string(),
+            values: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, sql: &str) -> &mut Self {
+        self.sql.push_str(sql);
+        self
+    }
+
+    fn bind_text(&mut self, value: impl Into<String>) -> &mut Self {
+        self.values.push(Value::Text(value.into()));
+        let _ = write!(self.sql, "${}", self.values.len());
+        self
+    }
+
+    fn bind_int(&mut self, value: i64) -> &mut Self {
+        self.values.push(Value::Int(value));
+        let _ = write!(self.sql, "${}", self.values.len());
+        self
+    }
+
+    fn query(&self) -> Query<'_, Any, AnyArguments<'_>> {
+        let mut query = sqlx::query(&self.sql);
+        for value in &self.values {
+            query = match value {
+                Value::Text(text) => query.bind(text.as_str()),
+                Value::Int(int) => query.bind(*int),
+            };
+        }
+        query
+    }
+}
+
+impl SqlStore {
+    /// Connects to the database at `url` and applies pending migrations.
+    /// Records are deleted `retention_days` after the certificate expires;
+    /// 0 keeps them forever.
+    pub async fn connect(url: &str, max_connections: u32, retention_days: u32) -> Result<Self> {
+        sqlx::any::install_default_drivers();
+        let pool = AnyPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(url)
+            .await?;
+
+        MIGRATOR
+            .run(&pool)
+            .await
+            .map_err(|e| CertAgentError::Database(sqlx::Error::Migrate(Box::new(e))))?;
+
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Ok(Self {
+            pool,
+            retention_seconds: Some(retention_days as i64 * 24 * 60 * 60)
+                .filter(|seconds| *seconds > 0),
+            events,
+        })
+    }
+
+    /// Applies `change` to a certificate and writes it back unless another
+    /// writer changed its status first, in which case the change is applied
+    /// again to the fresh record. `change` returns `false` to leave the
+    /// record alone. Returns `None` if the certificate does not exist.
+    async fn modify(
+        &self,
+        certificate_id: &str,
+        change: impl Fn(&mut CertificateRecord) -> bool + Send + Sync,
+    ) -> Result<Option<bool>> {
+        loop {
+            let Some(mut cert_record) = self.get_certificate(certificate_id).await? else {
+                return Ok(None);
+            };
+            let previous_status = cert_record.status.clone();
+            if !change(&mut cert_record) {
+                return Ok(Some(false));
+            }
+
+            let updated = sqlx::query(
+                "UPDATE certificates SET status = $1, revoked_at = $2, revocation_reason = $3, \
+                 record = $4 WHERE certificate_id = $5 AND status = $6",
+            )
+            .bind(cert_record.status.as_str())
+            .bind(cert_record.revoked_at)
+            .bind(cert_record.revocation_reason.as_deref())
+            .bind(serde_json::to_string(&cert_record)?)
+            .bind(certificate_id)
+            .bind(previous_status.as_str())
+            .execute(&self.pool)
+            .await?;
+
+            if updated.rows_affected() > 0 {
+                return Ok(Some(true));
+            }
+        }
+    }
+
+    async fn active_expiring_between(
+        &self,
+        after: Option<i64>,
+        until: i64,
+    ) -> Result<Vec<CertificateRecord>> {
+        let mut statement =
+            Statement::new("SELECT record FROM certificates WHERE status = 'active'");
+        if let Some(after) = after {
+            statement.push(" AND expires_at > ").bind_int(after);
+        }
+        statement
+            .push(" AND expires_at <= ")
+            .bind_int(until)
+            .push(" ORDER BY expires_at, issued_at, certificate_id");
+
+        let rows = statement.query().fetch_all(&self.pool).await?;
+        rows.iter().map(record_from_row).collect()
+    }
+
+    async fn get_state(&self, name: &str) -> Result<Option<String>> {
+        let row = sqlx::query("SELECT value FROM agent_state WHERE name = $1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| row.try_get("value")).transpose()?)
+    }
+
+    async fn set_state(&self, name: &str, value: String) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO agent_state (name, value) VALUES ($1, $2) \
+             ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+        )
+        .bind(name)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CertificateStore for SqlStore {
+    async fn record_issuance(&self, cert_record: &CertificateRecord) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(predecessor_id) = &cert_record.predecessor_id {
+            let row = sqlx::query("SELECT record FROM certificates WHERE certificate_id = $1")
+                .bind(predecessor_id.as_str())
+                .fetch_optional(&mut *tx)
+                .await?;
+            let Some(mut predecessor) = row.as_ref().map(record_from_row).transpose()? else {
+                return Ok(false);
+            };
+            if !mark_superseded(
+                &mut predecessor,
+                &cert_record.certificate_id,
+                cert_record.issued_at,
+            ) {
+                return Ok(false);
+            }
+
+            // The status guard keeps a concurrent revocation or renewal
+            let updated = sqlx::query(
+                "UPDATE certificates SET status = $1, record = $2 \
+                 WHERE certificate_id = $3 AND status = 'active'",
+            )
+            .bind(predecessor.status.as_str())
+            .bind(serde_json::to_string(&predecessor)?)
+            .bind(predecessor_id.as_str())
+            .execute(&mut *tx)
+            .await?;
+            if updated.rows_affected() == 0 {
+                return Ok(false);
+            }
+        }
+
+        write_record(&mut tx, cert_record).await?;
+        tx.commit().await?;
+
+        for (event, data) in issuance_events(cert_record) {
+            self.publish_event(event, data).await?;
+        }
+        Ok(true)
+    }
+
+    async fn get_certificate(&self, certificate_id: &str) -> Result<Option<CertificateRecord>> {
+        let row = sqlx::query("SELECT record FROM certificates WHERE certificate_id = $1")
+            .bind(certificate_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.as_ref().map(record_from_row).transpose()
+    }
+
+    async fn get_certificate_by_serial(
+        &self,
+        serial_number: &str,
+    ) -> Result<Option<CertificateRecord>> {
+        let row = sqlx::query("SELECT record FROM certificates WHERE serial_number = $1")
+            .bind(serial_number)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.as_ref().map(record_from_row).transpose()
+    }
+
+    async fn record_revocation(
+        &self,
+        certificate_id: &str,
+        revoked_at: i64,
+        reason: Option<&str>,
+    ) -> Result<Option<bool>> {
+        self.modify(certificate_id, |cert_record| {
+            mark_revoked(cert_record, revoked_at, reason)
+        })
+        .await
+    }
+
+    async fn supersede(
+        &self,
+        certificate_id: &str,
+        successor_id: &str,
+        superseded_at: i64,
+    ) -> Result<bool> {
+        let updated = self
+            .modify(certificate_id, |cert_record| {
+                mark_superseded(cert_record, successor_id, superseded_at)
+            })
+            .await?;
+        Ok(updated == Some(true))
+    }
+
+    /// Keyset pagination over the sort columns, with `certificate_id` as the
+    /// tie-breaker; page tokens have the same shape as the Redis store's.
+    async fn list_certificates_page(
+        &self,
+        query: &CertificateQuery,
+        page_size: usize,
+        page_token: Option<&str>,
+    ) -> Result<CertificatePage> {
+        let cursor = page_token
+            .map(|token| decode_page_token(token, query.sort))
+            .transpose()?;
+
+        let mut statement = Statement::new("SELECT record FROM certificates c WHERE 1 = 1");
+        if let Some(status) = &query.status {
+            statement.push(" AND c.status = ").bind_text(status);
+        }
+        if let Some(common_name) = &query.common_name {
+            statement
+                .push(" AND c.common_name_lower = ")
+                .bind_text(common_name.to_lowercase());
+        }
+        if let Some(prefix) = &query.common_name_prefix {
+            let pattern = prefix
+                .to_lowercase()
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            statement
+                .push(" AND c.common_name_lower LIKE ")
+                .bind_text(format!("{}%", pattern))
+                .push(" ESCAPE '\\'");
+        }
+        if let Some(dns_name) = &query.dns_name {
+            statement
+                .push(
+                    " AND EXISTS (SELECT 1 FROM certificate_dns_names d \
+                     WHERE d.certificate_id = c.certificate_id AND d.dns_name = ",
+                )
+                .bind_text(dns_name.to_ascii_lowercase())
+                .push(")");
+        }
+        if let Some(ip_address) = &query.ip_address {
+            statement
+                .push(
+                    " AND EXISTS (SELECT 1 FROM certificate_ip_addresses i \
+                     WHERE i.certificate_id = c.certificate_id AND i.ip_address = ",
+                )
+                .bind_text(canonical_ip(ip_address))
+                .push(")");
+        }
+        for (name, value) in &query.metadata {
+            statement
+                .push(
+                    " AND EXISTS (SELECT 1 FROM certificate_metadata m \
+                     WHERE m.certificate_id = c.certificate_id AND m.name = ",
+                )
+                .bind_text(name)
+                .push(" AND m.value = ")
+                .bind_text(value)
+                .push(")");
+        }
+        if let Some(issuer_key_id) = &query.issuer_key_id {
+            statement
+                .push(" AND c.issuer_key_id = ")
+                .bind_text(issuer_key_id.to_ascii_uppercase());
+        }
+        if let Some(profile) = &query.profile {
+            statement.push(" AND c.profile = ").bind_text(profile);
+        }
+        if let Some(lineage_id) = &query.lineage_id {
+            statement.push(" AND c.lineage_id = ").bind_text(lineage_id);
+        }
+        for (column, after, before) in [
+            ("c.issued_at", query.issued_after, query.issued_before),
+            ("c.expires_at", query.expires_after, query.expires_before),
+        ] {
+            if let Some(after) = after {
+                statement
+                    .push(&format!(" AND {} >= ", column))
+                    .bind_int(after);
+            }
+            if let Some(before) = before {
+                statement
+                    .push(&format!(" AND {} < ", column))
+                    .bind_int(before);
+            }
+        }
+
+        let (by_expiry, direction, order) = match query.sort {
+            ListSort::IssuedAsc => (false, ">", "ASC"),
+            ListSort::IssuedDesc => (false, "<", "DESC"),
+            ListSort::ExpiresAsc => (true, ">", "ASC"),
+            ListSort::ExpiresDesc => (true, "<", "DESC"),
+        };
+        if let Some((member, expires_at)) = &cursor {
+            let (issued_at, certificate_id) = split_page_member(member)?;
+
+            statement.push(" AND (");
+            if by_expiry {
+                statement
+                    .push(&format!("c.expires_at {} ", direction))
+                    .bind_int(*expires_at)
+                    .push(" OR (c.expires_at = ")
+                    .bind_int(*expires_at)
+                    .push(" AND (");
+            }
+            statement
+                .push(&format!("c.issued_at {} ", direction))
+                .bind_int(issued_at)
+                .push(" OR (c.issued_at = ")
+                .bind_int(issued_at)
+                .push(&format!(" AND c.certificate_id {} ", direction))
+                .bind_text(certificate_id)
+                .push(")");
+            if by_expiry {
+                statement.push("))");
+            }
+            statement.push(")");
+        }
+
+        statement.push(" ORDER BY ");
+        if by_expiry {
+            statement.push(&format!("c.expires_at {}, ", order));
+        }
+        statement
+            .push(&format!(
+                "c.issued_at {}, c.certificate_id {} LIMIT ",
+                order, order
+            ))
+            // One extra row tells whether there is another page
+            .bind_int(page_size as i64 + 1);
+
+        let rows = statement.query().fetch_all(&self.pool).await?;
+        let mut certificates = rows
+            .iter()
+            .map(record_from_row)
+            .collect::<Result<Vec<_>>>()?;
+
+        let next_page_token = if certificates.len() > page_size {
+            certificates.truncate(page_size);
+            certificates.last().map(|last| {
+                let member = format!("{:020}:{}", last.issued_at.max(0), last.certificate_id);
+                let score = if by_expiry { last.expires_at } else { 0 };
+                encode_page_token(query.sort, &(member, score))
+            })
+        } else {
+            None
+        };
+
+        Ok(CertificatePage {
+            certificates,
+            next_page_token,
+        })
+    }
+
+    async fn get_expiring_certificates(
+        &self,
+        threshold_days: u32,
+    ) -> Result<Vec<CertificateRecord>> {
+        let current_time = chrono::Utc::now().timestamp();
+        let threshold_seconds = (threshold_days as i64) * 24 * 60 * 60;
+        self.active_expiring_between(Some(current_time), current_time + threshold_seconds)
+            .await
+    }
+
+    async fn get_lapsed_certificates(&self) -> Result<Vec<CertificateRecord>> {
+        self.active_expiring_between(None, chrono::Utc::now().timestamp())
+            .await
+    }
+
+    async fn expire_certificate(&self, certificate_id: &str) -> Result<bool> {
+        let current_time = chrono::Utc::now().timestamp();
+        let expired = self
+            .modify(certificate_id, |cert_record| {
+                if cert_record.status != "active" || cert_record.expires_at > current_time {
+                    return false;
+                }
+                cert_record.status = "expired".to_string();
+                true
+            })
+            .await?;
+        Ok(expired == Some(true))
+    }
+
+    async fn delete_certificate(&self, certificate_id: &str) -> Result<()> {
+        // Search table rows go with it through ON DELETE CASCADE
+        sqlx::query("DELETE FROM certificates WHERE certificate_id = $1")
+            .bind(certificate_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The database keeps its own indexes consistent, so the repair only
+    /// deletes records past their retention window, as TTLs do in Redis.
+    async fn repair(&self) -> Result<StoreRepair> {
+        let mut repair = StoreRepair::default();
+
+        if let Some(retention) = self.retention_seconds {
+            let deleted = sqlx::query("DELETE FROM certificates WHERE expires_at < $1")
+                .bind(chrono::Utc::now().timestamp() - retention)
+                .execute(&self.pool)
+                .await?;
+            repair.removed_entries = deleted.rows_affected() as usize;
+        }
+
+        sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= $1")
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(repair)
+    }
+
+    async fn next_crl_number(&self) -> Result<u64> {
+        let row = sqlx::query(
+            "INSERT INTO counters (name, value) VALUES ('crl_number', 1) \
+             ON CONFLICT (name) DO UPDATE SET value = counters.value + 1 RETURNING value",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.try_get::<i64, _>("value")? as u64)
+    }
+
+    async fn store_crl(&self, issuer_key_id: &str, crl_der: &[u8]) -> Result<()> {
+        self.set_state(&crl_state(issuer_key_id), STANDARD.encode(crl_der))
+            .await
+    }
+
+    async fn get_crl(&self, issuer_key_id: &str) -> Result<Option<Vec<u8>>> {
+        self.get_state(&crl_state(issuer_key_id))
+            .await?
+            .map(|crl| {
+                STANDARD
+                    .decode(crl)
+                    .map_err(|e| CertAgentError::Internal(format!("Stored CRL is corrupt: {}", e)))
+            })
+            .transpose()
+    }
+
+    async fn get_ca_rotation(&self) -> Result<Option<CaRotationRecord>> {
+        match self.get_state("ca_rotation").await? {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn store_ca_rotation(&self, rotation: &CaRotationRecord) -> Result<()> {
+        self.set_state("ca_rotation", serde_json::to_string(rotation)?)
+            .await
+    }
+
+    async fn delete_ca_rotation(&self) -> Result<()> {
+        sqlx::query("DELETE FROM agent_state WHERE name = 'ca_rotation'")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        ttl_seconds: u64,
+    ) -> Result<Option<IdempotencyRecord>> {
+        let value = serde_json::to_string(record)?;
+
+        loop {
+            let now = chrono::Utc::now().timestamp();
+            // Rows past their expiry are taken over; repair deletes the rest
+            let claimed = sqlx::query(
+                "INSERT INTO idempotency_keys (idempotency_key, record, expires_at) \
+                 VALUES ($1, $2, $3) \
+                 ON CONFLICT (idempotency_key) DO UPDATE SET record = excluded.record, \
+                 expires_at = excluded.expires_at WHERE idempotency_keys.expires_at <= $4",
+            )
+            .bind(key)
+            .bind(value.as_str())
+            .bind(now + ttl_seconds as i64)
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+            if claimed.rows_affected() > 0 {
+                return Ok(None);
+            }
+
+            let existing = sqlx::query(
+                "SELECT record FROM idempotency_keys \
+                 WHERE idempotency_key = $1 AND expires_at > $2",
+            )
+            .bind(key)
+            .bind(now)
+            .fetch_optional(&self.pool)
+            .await?;
+            // Released or expired in between: try to claim it again
+            if let Some(row) = existing {
+                let existing: String = row.try_get("record")?;
+                return Ok(Some(serde_json::from_str(&existing)?));
+            }
+        }
+    }
+
+    async fn store_idempotency_record(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        ttl_seconds: u64,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO idempotency_keys (idempotency_key, record, expires_at) \
+             VALUES ($1, $2, $3) \
+             ON CONFLICT (idempotency_key) DO UPDATE SET record = excluded.record, \
+             expires_at = excluded.expires_at",
+        )
+        .bind(key)
+        .bind(serde_json::to_string(record)?)
+        .bind(chrono::Utc::now().timestamp() + ttl_seconds as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<()> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE idempotency_key = $1")
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn publish_event(&self, event: &str, data: &str) -> Result<()> {
+        // No subscribers is not an error
+        let _ = self.events.send((event.to_string(), data.to_string()));
+        Ok(())
+    }
+
+    async fn subscribe_events(&self) -> Result<EventStream> {
+        Ok(broadcast_events(self.events.subscribe()))
+    }
+}
+
+/// Inserts or replaces a certificate row and rewrites its search rows.
+async fn write_record(
+    tx: &mut sqlx::Transaction<'_, Any>,
+    cert_record: &CertificateRecord,
+) -> Result<()> {
+    let id = cert_record.certificate_id.as_str();
+
+    sqlx::query(
+        "INSERT INTO certificates (certificate_id, common_name, common_name_lower, status, \
+         issued_at, expires_at, serial_number, fingerprint_sha256, signature_algorithm, \
+         issuer_key_id, profile, owner, revoked_at, revocation_reason, lineage_id, record) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16) \
+         ON CONFLICT (certificate_id) DO UPDATE SET common_name = excluded.common_name, \
+         common_name_lower = excluded.common_name_lower, status = excluded.status, \
+         issued_at = excluded.issued_at, expires_at = excluded.expires_at, \
+         serial_number = excluded.serial_number, \
+         fingerprint_sha256 = excluded.fingerprint_sha256, \
+         signature_algorithm = excluded.signature_algorithm, \
+         issuer_key_id = excluded.issuer_key_id, profile = excluded.profile, \
+         owner = excluded.owner, revoked_at = excluded.revoked_at, \
+         revocation_reason = excluded.revocation_reason, \
+         lineage_id = excluded.lineage_id, record = excluded.record",
+    )
+    .bind(id)
+    .bind(cert_record.common_name.as_str())
+    .bind(cert_record.common_name.to_lowercase())
+    .bind(cert_record.status.as_str())
+    .bind(cert_record.issued_at)
+    .bind(cert_record.expires_at)
+    .bind(cert_record.serial_number.as_str())
+    .bind(cert_record.fingerprint_sha256.as_str())
+    .bind(cert_record.signature_algorithm.as_str())
+    .bind(cert_record.issuer_key_id.to_ascii_uppercase())
+    .bind(cert_record.profile.as_str())
+    .bind(cert_record.owner.as_str())
+    .bind(cert_record.revoked_at)
+    .bind(cert_record.revocation_reason.as_deref())
+    .bind(cert_record.lineage())
+    .bind(serde_json::to_string(cert_record)?)
+    .execute(&mut **tx)
+    .await?;
+
+    // Search tables are rewritten whole
+    for table in [
+        "certificate_dns_names",
+        "certificate_ip_addresses",
+        "certificate_metadata",
+    ] {
+        sqlx::query(&format!("DELETE FROM {} WHERE certificate_id = $1", table))
+            .bind(id)
+            .execute(&mut **tx)
+            .await?;
+    }
+    for dns_name in &cert_record.dns_names {
+        sqlx::query(
+            "INSERT INTO certificate_dns_names (certificate_id, dns_name) VALUES ($1, $2) \
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(id)
+        .bind(dns_name.to_ascii_lowercase())
+        .execute(&mut **tx)
+        .await?;
+    }
+    for ip_address in &cert_record.ip_addresses {
+        sqlx::query(
+            "INSERT INTO certificate_ip_addresses (certificate_id, ip_address) \
+             VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(id)
+        .bind(canonical_ip(ip_address))
+        .execute(&mut **tx)
+        .await?;
+    }
+    for (name, value) in &cert_record.metadata {
+        sqlx::query(
+            "INSERT INTO certificate_metadata (certificate_id, name, value) \
+             VALUES ($1, $2, $3)",
+        )
+        .bind(id)
+        .bind(name.as_str())
+        .bind(value.as_str())
+        .execute(&mut **tx)
+        .await?;
+    }
+    Ok(())
+}
+
+/// `agent_state` entry holding the current CRL of a CA.
+fn crl_state(issuer_key_id: &str) -> String {
+    format!("crl:{}", issuer_key_id)
+}
+
+fn record_from_row(row: &sqlx::any::AnyRow) -> Result<CertificateRecord> {
+    let record: String = row.try_get("record")?;
+    Ok(serde_json::from_str(&record)?)
+}
@@ -0,0 +1,385 @@
+use crate::certificate::{CertificateManager, CertificateRequest};
+use crate::config::TlsConfig;
+use crate::error::{CertAgentError, Result};
+use crate::keys;
+use openssl::{asn1::Asn1Time, pkey::PKey, x509::X509};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::fs;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+    RootCertStore, ServerConfig,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, info, warn};
+
+/// Time a client gets to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The gRPC serving certificate. New connections pick up a certificate the
+/// watcher re-issues, or an operator replaces on disk, without a restart.
+#[derive(Debug, Clone)]
+pub struct ServingCertificate {
+    config: TlsConfig,
+    cert_manager: CertificateManager,
+    provider: Arc<CryptoProvider>,
+    resolver: Arc<ServingCertResolver>,
+    server_config: Arc<ServerConfig>,
+}
+
+/// Hands every handshake the certificate loaded last.
+#[derive(Debug)]
+struct ServingCertResolver {
+    /// Certificate file contents the key was loaded from
+    current: RwLock<(Vec<u8>, Arc<CertifiedKey>)>,
+}
+
+impl ResolvesServerCert for ServingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().1.clone())
+    }
+}
+
+impl ServingCertificate {
+    /// Loads the gRPC server TLS settings, first issuing the serving
+    /// certificate from the agent's CA when `bootstrap` is enabled.
+    pub async fn load(config: &TlsConfig, cert_manager: &CertificateManager) -> Result<Self> {
+        if config.bootstrap && needs_bootstrap(config, cert_manager).await? {
+            bootstrap_certificate(config, cert_manager).await?;
+        }
+
+        let provider = Arc::new(ring::default_provider());
+        let cert_pem = fs::read(&config.cert_file).await?;
+        let certified_key = load_certified_key(&provider, &cert_pem, &config.key_file).await?;
+        let resolver = Arc::new(ServingCertResolver {
+            current: RwLock::new((cert_pem, certified_key)),
+        });
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+        let builder = match &config.ca_file {
+            Some(ca_file) => {
+                let mut roots = RootCertStore::empty();
+                for ca in X509::stack_from_pem(&fs::read(ca_file).await?)? {
+                    roots
+                        .add(CertificateDer::from(ca.to_der()?))
+                        .map_err(tls_error)?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone());
+                let verifier = if config.client_auth_optional {
+                    verifier.allow_unauthenticated()
+                } else {
+                    verifier
+                }
+                .build()
+                .map_err(tls_error)?;
+                info!(
+                    "gRPC mutual TLS enabled, client certificates verified against {}{}",
+                    ca_file,
+                    if config.client_auth_optional {
+                        " (optional)"
+                    } else {
+                        ""
+                    }
+                );
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => {
+                info!("gRPC TLS enabled without client certificate verification");
+                builder.with_no_client_auth()
+            }
+        };
+        let mut server_config = builder.with_cert_resolver(resolver.clone());
+        server_config.alpn_protocols.push(b"h2".to_vec());
+
+        Ok(Self {
+            config: config.clone(),
+            cert_manager: cert_manager.clone(),
+            provider,
+            resolver,
+            server_config: Arc::new(server_config),
+        })
+    }
+
+    /// Re-issues the certificate when `bootstrap` is enabled and it is close
+    /// to expiry or no longer verifies against the active CA, then serves
+    /// the certificate files if they changed. Returns the expiry of a
+    /// certificate that is due but left to the operator to replace.
+    pub async fn refresh(&self) -> Result<Option<String>> {
+        let mut due = None;
+        if needs_bootstrap(&self.config, &self.cert_manager).await? {
+            if self.config.bootstrap {
+                bootstrap_certificate(&self.config, &self.cert_manager).await?;
+            } else {
+                let cert = X509::from_pem(&fs::read(&self.config.cert_file).await?)?;
+                due = Some(cert.not_after().to_string());
+            }
+        }
+
+        let cert_pem = fs::read(&self.config.cert_file).await?;
+        if self.resolver.current.read().unwrap().0 != cert_pem {
+            let certified_key =
+                load_certified_key(&self.provider, &cert_pem, &self.config.key_file).await?;
+            *self.resolver.current.write().unwrap() = (cert_pem, certified_key);
+            info!(
+                "Reloaded gRPC serving certificate from {}",
+                self.config.cert_file
+            );
+        }
+
+        Ok(due)
+    }
+
+    /// Accepts TLS connections on `listener`, completing handshakes in the
+    /// background so that a slow client does not hold up the others.
+    pub fn incoming(
+        &self,
+        listener: TcpListener,
+    ) -> ReceiverStream<std::io::Result<TlsStream<TcpStream>>> {
+        let acceptor = TlsAcceptor::from(self.server_config.clone());
+        let (tx, rx) = mpsc::channel(64);
+
+        tokio::spawn(async move {
+            while !tx.is_closed() {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept gRPC connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                if let Err(e) = stream.set_nodelay(true) {
+                    debug!("Failed to set TCP_NODELAY: {}", e);
+                }
+
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send(Ok(stream)).await;
+                        }
+                        Ok(Err(e)) => debug!("gRPC TLS handshake failed: {}", e),
+                        Err(_) => debug!("gRPC TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+
+    #[cfg(test)]
+    fn served_certificate(&self) -> CertificateDer<'static> {
+        self.resolver.current.read().unwrap().1.cert[0].clone()
+    }
+}
+
+async fn load_certified_key(
+    provider: &CryptoProvider,
+    cert_pem: &[u8],
+    key_file: &str,
+) -> Result<Arc<CertifiedKey>> {
+    let chain = X509::stack_from_pem(cert_pem)?
+        .iter()
+        .map(|cert| Ok(CertificateDer::from(cert.to_der()?)))
+        .collect::<Result<Vec<_>>>()?;
+    let key = PKey::private_key_from_pem(&fs::read(key_file).await?)?;
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.private_key_to_pkcs8()?));
+
+    Ok(Arc::new(
+        CertifiedKey::from_der(chain, key, provider).map_err(tls_error)?,
+    ))
+}
+
+fn tls_error(e: impl std::fmt::Display) -> CertAgentError {
+    CertAgentError::Certificate(format!("Invalid gRPC TLS config: {}", e))
+}
+
+async fn needs_bootstrap(config: &TlsConfig, cert_manager: &CertificateManager) -> Result<bool> {
+    if !Path::new(&config.cert_file).exists() || !Path::new(&config.key_file).exists() {
+        return Ok(true);
+    }
+
+    let cert = X509::from_pem(&fs::read(&config.cert_file).await?)?;
+    let renew_before = Asn1Time::days_from_now(cert_manager.config().renewal_threshold_days)?;
+    let ca_key = cert_manager.ca().cert.public_key()?;
+
+    Ok(cert.not_after() < renew_before || !cert.verify(&ca_key)?)
+}
+
+async fn bootstrap_certificate(
+    config: &TlsConfig,
+    cert_manager: &CertificateManager,
+) -> Result<()> {
+    let common_name = config.server_names.first().cloned().ok_or_else(|| {
+        CertAgentError::InvalidRequest(
+            "grpc.tls.server_names is required to bootstrap a serving certificate".to_string(),
+        )
+    })?;
+    let (ip_addresses, dns_names) = config
+        .server_names
+        .iter()
+        .cloned()
+        .partition(|name| name.parse::<IpAddr>().is_ok());
+
+    let request = CertificateRequest {
+        common_name,
+        dns_names,
+        ip_addresses,
+        validity_days: 0,
+        organization: None,
+        organizational_unit: None,
+        country: None,
+        state: None,
+        locality: None,
+        metadata: HashMap::from([("purpose".to_string(), "grpc-server".to_string())]),
+        key_algorithm: None,
+        profile: Some("server".to_string()),
+        owner: None,
+    };
+    // The certificate being replaced, whose record the new one supersedes
+    let current = match fs::read(&config.cert_file).await {
+        Ok(pem) => X509::from_pem(&pem).ok(),
+        Err(_) => None,
+    };
+    let issued = cert_manager
+        .issue_tls_bootstrap_certificate(request, current.as_ref())
+        .await?;
+
+    // Serve the intermediates along with the leaf so clients only need the root
+    let mut cert_pem = issued.certificate_pem;
+    for ca_pem in &issued.ca_chain_pem {
+        cert_pem.push_str(ca_pem);
+    }
+
+    if let Some(parent) = Path::new(&config.cert_file).parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::write(&config.cert_file, cert_pem).await?;
+    // Left unencrypted: the TLS stack needs the plain PEM key
+    keys::write_private_file(
+        &config.key_file,
+        issued.private_key_pem.unwrap_or_default().as_bytes(),
+    )
+    .await?;
+
+    info!(
+        "Bootstrapped gRPC serving certificate {} from the agent CA",
+        issued.certificate_id
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::tests::{request, test_manager_with};
+    use crate::store::CertificateStore;
+
+    fn tls_config(dir: &Path, bootstrap: bool) -> TlsConfig {
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        TlsConfig {
+            cert_file: path("grpc/server.crt"),
+            key_file: path("grpc/server.key"),
+            ca_file: None,
+            client_auth_optional: false,
+            bootstrap,
+            server_names: vec!["agent.example.com".to_string(), "127.0.0.1".to_string()],
+        }
+    }
+
+    fn leaf_der(path: &str) -> CertificateDer<'static> {
+        let cert = X509::from_pem(&std::fs::read(path).unwrap()).unwrap();
+        CertificateDer::from(cert.to_der().unwrap())
+    }
+
+    #[tokio::test]
+    async fn reissues_and_serves_a_due_certificate_in_the_same_lineage() {
+        // Every certificate is due for renewal as soon as it is issued
+        let (cert_manager, store, dir) = test_manager_with(|config| {
+            config.certificate.renewal_threshold_days = 10_000;
+        })
+        .await;
+        let config = tls_config(dir.path(), true);
+
+        let serving = ServingCertificate::load(&config, &cert_manager)
+            .await
+            .unwrap();
+        let first = store.list_certificates(Some("active")).await.unwrap();
+        assert_eq!(first.len(), 1);
+        assert!(first[0].tls_bootstrap);
+        assert_eq!(serving.served_certificate(), leaf_der(&config.cert_file));
+
+        assert_eq!(serving.refresh().await.unwrap(), None);
+
+        let active = store.list_certificates(Some("active")).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_ne!(active[0].certificate_id, first[0].certificate_id);
+        assert_eq!(
+            active[0].predecessor_id.as_deref(),
+            Some(first[0].certificate_id.as_str())
+        );
+        assert!(active[0].tls_bootstrap);
+        let superseded = store
+            .get_certificate(&first[0].certificate_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(superseded.status, "superseded");
+        assert_eq!(serving.served_certificate(), leaf_der(&config.cert_file));
+    }
+
+    #[tokio::test]
+    async fn reloads_replaced_files_and_reports_a_due_certificate() {
+        let (cert_manager, store, dir) = test_manager_with(|config| {
+            config.certificate.renewal_threshold_days = 10_000;
+        })
+        .await;
+        let config = tls_config(dir.path(), false);
+        let install = |issued: crate::certificate::IssuedCertificate| {
+            std::fs::create_dir_all(dir.path().join("grpc")).unwrap();
+            std::fs::write(&config.cert_file, issued.certificate_pem).unwrap();
+            std::fs::write(&config.key_file, issued.private_key_pem.unwrap()).unwrap();
+        };
+        install(
+            cert_manager
+                .issue_certificate(request("agent.example.com"))
+                .await
+                .unwrap(),
+        );
+        let serving = ServingCertificate::load(&config, &cert_manager)
+            .await
+            .unwrap();
+        let loaded = serving.served_certificate();
+
+        install(
+            cert_manager
+                .issue_certificate(request("agent.example.com"))
+                .await
+                .unwrap(),
+        );
+        let due = serving.refresh().await.unwrap();
+
+        // Left to the operator: nothing is issued, the new files are served
+        assert!(due.is_some());
+        assert_eq!(
+            store.list_certificates(Some("active")).await.unwrap().len(),
+            2
+        );
+        assert_ne!(serving.served_certificate(), loaded);
+        assert_eq!(serving.served_certificate(), leaf_der(&config.cert_file));
+    }
+}
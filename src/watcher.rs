@@ -1,27 +1,38 @@
-use crate::certificate::CertificateManager;
+use crate::certificate::{CertificateManager, RenewalOverrides};
 use crate::config::WatcherConfig;
 use crate::error::Result;
-use crate::redis_client::RedisClient;
+use crate::rotation::CaRotation;
+use crate::store::{CertificateQuery, CertificateRecord, CertificateStore};
+use crate::tls::ServingCertificate;
 use std::sync::Arc;
 use tokio::sync::Semaphore;
 use tracing::{error, info, warn};
 
+/// Expired certificates deleted per listing page during cleanup.
+const CLEANUP_BATCH_SIZE: usize = 500;
+
 #[derive(Debug, Clone)]
 pub struct CertificateWatcher {
     cert_manager: CertificateManager,
-    redis: RedisClient,
+    store: Arc<dyn CertificateStore>,
+    rotation: CaRotation,
+    serving_certificate: Option<ServingCertificate>,
     config: WatcherConfig,
 }
 
 impl CertificateWatcher {
     pub fn new(
         cert_manager: CertificateManager,
-        redis: RedisClient,
+        store: Arc<dyn CertificateStore>,
+        rotation: CaRotation,
+        serving_certificate: Option<ServingCertificate>,
         config: WatcherConfig,
     ) -> Self {
         Self {
             cert_manager,
-            redis,
+            store,
+            rotation,
+            serving_certificate,
             config,
         }
     }
@@ -38,17 +49,101 @@ impl CertificateWatcher {
 
         // Semaphore to limit concurrent renewals
         let renewal_semaphore = Arc::new(Semaphore::new(self.config.max_concurrent_renewals));
+        let repair_interval =
+            tokio::time::Duration::from_secs(self.config.index_repair_interval_seconds);
+        let mut last_repair: Option<tokio::time::Instant> = None;
 
         loop {
             interval.tick().await;
 
+            if let Err(e) = self.expire_lapsed_certificates().await {
+                error!("Error expiring certificates: {}", e);
+            }
+
+            if self.config.expired_retention_days > 0 {
+                if let Err(e) = self
+                    .cleanup_expired_certificates(self.config.expired_retention_days)
+                    .await
+                {
+                    error!("Error cleaning up expired certificates: {}", e);
+                }
+            }
+
             if let Err(e) = self
                 .check_and_renew_certificates(renewal_semaphore.clone())
                 .await
             {
                 error!("Error in certificate watcher: {}", e);
             }
+
+            if let Err(e) = self
+                .reissue_for_ca_rotation(renewal_semaphore.clone())
+                .await
+            {
+                error!("Error re-issuing certificates for CA rotation: {}", e);
+            }
+
+            if let Err(e) = self.refresh_serving_certificate().await {
+                error!("Error refreshing the gRPC serving certificate: {}", e);
+            }
+
+            if !repair_interval.is_zero()
+                && last_repair.is_none_or(|last| last.elapsed() >= repair_interval)
+            {
+                last_repair = Some(tokio::time::Instant::now());
+                if let Err(e) = self.repair_store().await {
+                    error!("Error repairing certificate store: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Re-issues or reloads the gRPC serving certificate, warning when one
+    /// the agent does not issue itself is close to expiry.
+    async fn refresh_serving_certificate(&self) -> Result<()> {
+        let Some(serving_certificate) = &self.serving_certificate else {
+            return Ok(());
+        };
+
+        if let Some(not_after) = serving_certificate.refresh().await? {
+            warn!(
+                "gRPC serving certificate expires {}; replace it before then",
+                not_after
+            );
+            self.store
+                .publish_event("serving_certificate_expiring", &not_after)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Cleans up after records the store has expired or deleted and reports
+    /// records missing from its indexes.
+    async fn repair_store(&self) -> Result<()> {
+        let repair = self.store.repair().await?;
+
+        if repair.removed_entries > 0 {
+            info!(
+                "Removed {} dangling certificate store entries",
+                repair.removed_entries
+            );
+        }
+        if !repair.orphaned_records.is_empty() {
+            warn!(
+                "Found {} certificate records missing from the indexes: {}",
+                repair.orphaned_records.len(),
+                repair.orphaned_records.join(", ")
+            );
+            self.store
+                .publish_event(
+                    "orphaned_records",
+                    &repair.orphaned_records.len().to_string(),
+                )
+                .await?;
         }
+
+        Ok(())
     }
 
     async fn check_and_renew_certificates(&self, renewal_semaphore: Arc<Semaphore>) -> Result<()> {
@@ -65,12 +160,96 @@ impl CertificateWatcher {
             expiring_certs.len()
         );
 
+        self.renew_all(expiring_certs, renewal_semaphore, "auto_renewed")
+            .await;
+
+        Ok(())
+    }
+
+    /// Marks active certificates past their `expires_at` as expired.
+    async fn expire_lapsed_certificates(&self) -> Result<()> {
+        let lapsed = self.store.get_lapsed_certificates().await?;
+        let mut expired_count = 0;
+
+        for cert in lapsed {
+            // Skipped when revoked or renewed since it was listed
+            if !self.store.expire_certificate(&cert.certificate_id).await? {
+                continue;
+            }
+
+            info!("Certificate expired: {}", cert.certificate_id);
+            expired_count += 1;
+
+            if let Err(e) = self
+                .store
+                .publish_event("expired", &cert.certificate_id)
+                .await
+            {
+                warn!("Failed to publish expiry event: {}", e);
+            }
+        }
+
+        if expired_count > 0 {
+            info!("Marked {} certificates as expired", expired_count);
+        }
+
+        Ok(())
+    }
+
+    /// Moves this tick's share of certificates over to a newly activated CA.
+    async fn reissue_for_ca_rotation(&self, renewal_semaphore: Arc<Semaphore>) -> Result<()> {
+        let due = self
+            .rotation
+            .reissue_due(self.config.check_interval_seconds)
+            .await?;
+
+        if due.is_empty() {
+            return Ok(());
+        }
+
+        info!("Re-issuing {} certificates under the new CA", due.len());
+
+        self.renew_all(due, renewal_semaphore, "ca_rotation_reissued")
+            .await;
+
+        Ok(())
+    }
+
+    /// Renews `certs` concurrently, publishing `event` for each success.
+    /// Certificates whose key the client holds are left to the client, which
+    /// is asked to renew with a new CSR through a `renewal_required` event.
+    /// The agent's bootstrapped serving certificate is skipped: superseding
+    /// it would revoke the certificate the server still presents.
+    async fn renew_all(
+        &self,
+        certs: Vec<CertificateRecord>,
+        renewal_semaphore: Arc<Semaphore>,
+        event: &'static str,
+    ) {
+        let (client_keys, certs): (Vec<_>, Vec<_>) = certs
+            .into_iter()
+            .filter(|cert| !cert.tls_bootstrap)
+            .partition(|cert| cert.client_key);
+        for cert_record in client_keys {
+            info!(
+                "Certificate {} was signed from a CSR; waiting for its client to renew it",
+                cert_record.certificate_id
+            );
+            if let Err(e) = self
+                .store
+                .publish_event("renewal_required", &cert_record.certificate_id)
+                .await
+            {
+                warn!("Failed to publish renewal request event: {}", e);
+            }
+        }
+
         // Create tasks for concurrent renewal processing
         let mut renewal_tasks = Vec::new();
 
-        for cert_record in expiring_certs {
+        for cert_record in certs {
             let cert_manager = self.cert_manager.clone();
-            let redis = self.redis.clone();
+            let store = self.store.clone();
             let renewal_semaphore = renewal_semaphore.clone();
             let cert_id = cert_record.certificate_id.clone();
 
@@ -79,7 +258,10 @@ impl CertificateWatcher {
 
                 info!("Renewing certificate: {}", cert_id);
 
-                match cert_manager.renew_certificate(&cert_id, None).await {
+                match cert_manager
+                    .renew_certificate(&cert_id, None, RenewalOverrides::default())
+                    .await
+                {
                     Ok(new_cert) => {
                         info!(
                             "Successfully renewed certificate: {} -> {}",
@@ -87,10 +269,7 @@ impl CertificateWatcher {
                         );
 
                         // Publish renewal event
-                        if let Err(e) = redis
-                            .publish_event("auto_renewed", &new_cert.certificate_id)
-                            .await
-                        {
+                        if let Err(e) = store.publish_event(event, &new_cert.certificate_id).await {
                             warn!("Failed to publish renewal event: {}", e);
                         }
 
@@ -101,7 +280,7 @@ impl CertificateWatcher {
 
                         // Publish error event
                         let error_msg = format!("{}:{}", cert_id, e);
-                        if let Err(e) = redis.publish_event("renewal_failed", &error_msg).await {
+                        if let Err(e) = store.publish_event("renewal_failed", &error_msg).await {
                             warn!("Failed to publish renewal error event: {}", e);
                         }
 
@@ -135,8 +314,6 @@ impl CertificateWatcher {
             "Certificate renewal batch completed: {} successful, {} failed",
             successful_renewals, failed_renewals
         );
-
-        Ok(())
     }
 
     #[allow(dead_code)]
@@ -146,44 +323,59 @@ impl CertificateWatcher {
         let mut active_count = 0;
         let mut expired_count = 0;
         let mut revoked_count = 0;
+        let mut superseded_count = 0;
 
         for cert in all_certs {
             match cert.status.as_str() {
                 "active" => active_count += 1,
                 "expired" => expired_count += 1,
                 "revoked" => revoked_count += 1,
+                "superseded" => superseded_count += 1,
                 _ => {}
             }
         }
 
         info!(
-            "Certificate health check: {} active, {} expired, {} revoked",
-            active_count, expired_count, revoked_count
+            "Certificate health check: {} active, {} expired, {} revoked, {} superseded",
+            active_count, expired_count, revoked_count, superseded_count
         );
 
         // Publish health metrics
         let health_data = format!(
-            "active:{},expired:{},revoked:{}",
-            active_count, expired_count, revoked_count
+            "active:{},expired:{},revoked:{},superseded:{}",
+            active_count, expired_count, revoked_count, superseded_count
         );
-        self.redis
+        self.store
             .publish_event("health_check", &health_data)
             .await?;
 
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub async fn cleanup_expired_certificates(&self, days_old: u32) -> Result<()> {
         let cutoff_time = chrono::Utc::now().timestamp() - (days_old as i64 * 24 * 60 * 60);
-        let all_certs = self.cert_manager.list_certificates(Some("expired")).await?;
+        let query = CertificateQuery {
+            status: Some("expired".to_string()),
+            expires_before: Some(cutoff_time),
+            ..Default::default()
+        };
 
         let mut cleaned_count = 0;
+        let mut page_token = None;
 
-        for cert in all_certs {
-            if cert.expires_at < cutoff_time {
-                // Delete certificate files and Redis record
-                if let Err(e) = self.redis.delete_certificate(&cert.certificate_id).await {
+        loop {
+            let page = self
+                .cert_manager
+                .list_certificates_page(&query, CLEANUP_BATCH_SIZE, page_token.as_deref())
+                .await?;
+
+            for cert in page.certificates {
+                // Delete certificate files and stored record
+                if let Err(e) = self
+                    .cert_manager
+                    .delete_certificate(&cert.certificate_id)
+                    .await
+                {
                     warn!(
                         "Failed to delete expired certificate {}: {}",
                         cert.certificate_id, e
@@ -193,6 +385,11 @@ impl CertificateWatcher {
                     info!("Cleaned up expired certificate: {}", cert.certificate_id);
                 }
             }
+
+            match page.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => break,
+            }
         }
 
         if cleaned_count > 0 {
@@ -200,7 +397,7 @@ impl CertificateWatcher {
                 "Cleaned up {} expired certificates older than {} days",
                 cleaned_count, days_old
             );
-            self.redis
+            self.store
                 .publish_event("cleanup", &format!("removed:{}", cleaned_count))
                 .await?;
         }
@@ -208,3 +405,237 @@ impl CertificateWatcher {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::tests::{csr, request, test_manager};
+    use crate::certificate::CertificateRequest;
+    use crate::config::{Config, RotationConfig};
+    use crate::store::MemoryStore;
+    use futures::StreamExt;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn watcher(
+        cert_manager: &CertificateManager,
+        store: Arc<dyn CertificateStore>,
+    ) -> CertificateWatcher {
+        let rotation = CaRotation::new(
+            cert_manager.clone(),
+            store.clone(),
+            RotationConfig::default(),
+        );
+        CertificateWatcher::new(
+            cert_manager.clone(),
+            store,
+            rotation,
+            None,
+            Config::default().watcher,
+        )
+    }
+
+    /// Moves a stored certificate's expiry, as if it had been issued earlier.
+    async fn set_expiry(store: &MemoryStore, certificate_id: &str, expires_at: i64) {
+        let mut cert_record = store
+            .get_certificate(certificate_id)
+            .await
+            .unwrap()
+            .unwrap();
+        cert_record.issued_at += expires_at - cert_record.expires_at;
+        cert_record.expires_at = expires_at;
+        store.put_certificate(&cert_record);
+    }
+
+    #[tokio::test]
+    async fn marks_lapsed_certificates_expired() {
+        let (cert_manager, store, _dir) = test_manager().await;
+        let watcher = watcher(&cert_manager, store.clone());
+        let lapsed = cert_manager
+            .issue_certificate(request("old.example.com"))
+            .await
+            .unwrap();
+        let current = cert_manager
+            .issue_certificate(request("new.example.com"))
+            .await
+            .unwrap();
+        let now = chrono::Utc::now().timestamp();
+        set_expiry(store.as_ref(), &lapsed.certificate_id, now - 60).await;
+        let mut events = store.subscribe_events().await.unwrap();
+
+        watcher.expire_lapsed_certificates().await.unwrap();
+
+        let status = |id: String| {
+            let store = store.clone();
+            async move { store.get_certificate(&id).await.unwrap().unwrap().status }
+        };
+        assert_eq!(status(lapsed.certificate_id.clone()).await, "expired");
+        assert_eq!(status(current.certificate_id).await, "active");
+        assert_eq!(
+            events.next().await,
+            Some(("expired".to_string(), lapsed.certificate_id))
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_csr_signed_certificates_to_their_clients() {
+        let (cert_manager, store, _dir) = test_manager().await;
+        let watcher = watcher(&cert_manager, store.clone());
+        let signed = cert_manager
+            .sign_csr(&csr("api.example.com"), request("api.example.com"))
+            .await
+            .unwrap();
+        let now = chrono::Utc::now().timestamp();
+        set_expiry(store.as_ref(), &signed.certificate_id, now + 5 * DAY).await;
+        let mut events = store.subscribe_events().await.unwrap();
+
+        watcher
+            .check_and_renew_certificates(Arc::new(Semaphore::new(2)))
+            .await
+            .unwrap();
+
+        let active = store.list_certificates(Some("active")).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].certificate_id, signed.certificate_id);
+        assert_eq!(
+            events.next().await,
+            Some(("renewal_required".to_string(), signed.certificate_id))
+        );
+    }
+
+    #[tokio::test]
+    async fn renews_certificates_close_to_expiry() {
+        let (cert_manager, store, _dir) = test_manager().await;
+        let watcher = watcher(&cert_manager, store.clone());
+        let expiring = cert_manager
+            .issue_certificate(request("api.example.com"))
+            .await
+            .unwrap();
+        let healthy = cert_manager
+            .issue_certificate(request("web.example.com"))
+            .await
+            .unwrap();
+        let now = chrono::Utc::now().timestamp();
+        set_expiry(store.as_ref(), &expiring.certificate_id, now + 5 * DAY).await;
+
+        watcher
+            .check_and_renew_certificates(Arc::new(Semaphore::new(2)))
+            .await
+            .unwrap();
+
+        let old = store
+            .get_certificate(&expiring.certificate_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(old.status, "superseded");
+
+        let active = store.list_certificates(Some("active")).await.unwrap();
+        assert_eq!(active.len(), 2);
+        assert!(active
+            .iter()
+            .any(|c| c.certificate_id == healthy.certificate_id));
+        let renewed = active
+            .iter()
+            .find(|c| c.certificate_id != healthy.certificate_id)
+            .unwrap();
+        assert_eq!(renewed.common_name, "api.example.com");
+        assert!(renewed.expires_at > now + 300 * DAY);
+    }
+
+    #[tokio::test]
+    async fn leaves_the_bootstrapped_serving_certificate_alone() {
+        let (cert_manager, store, _dir) = test_manager().await;
+        let watcher = watcher(&cert_manager, store.clone());
+        let serving = cert_manager
+            .issue_tls_bootstrap_certificate(request("agent.example.com"), None)
+            .await
+            .unwrap();
+        let now = chrono::Utc::now().timestamp();
+        set_expiry(store.as_ref(), &serving.certificate_id, now + 5 * DAY).await;
+
+        watcher
+            .check_and_renew_certificates(Arc::new(Semaphore::new(2)))
+            .await
+            .unwrap();
+
+        let active = store.list_certificates(Some("active")).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].certificate_id, serving.certificate_id);
+        assert!(active[0].tls_bootstrap);
+    }
+
+    #[tokio::test]
+    async fn renews_short_lived_certificates_in_the_last_third_of_their_lifetime() {
+        let (cert_manager, store, _dir) = test_manager().await;
+        let watcher = watcher(&cert_manager, store.clone());
+        let workload = cert_manager
+            .issue_certificate(CertificateRequest {
+                profile: Some("workload".to_string()),
+                ..request("api.example.com")
+            })
+            .await
+            .unwrap();
+        let renew = || async {
+            watcher
+                .check_and_renew_certificates(Arc::new(Semaphore::new(2)))
+                .await
+                .unwrap();
+            store
+                .get_certificate(&workload.certificate_id)
+                .await
+                .unwrap()
+                .unwrap()
+                .status
+        };
+
+        // A fresh one-day certificate is well inside the 30-day threshold
+        assert_eq!(renew().await, "active");
+
+        let now = chrono::Utc::now().timestamp();
+        set_expiry(store.as_ref(), &workload.certificate_id, now + DAY / 4).await;
+        assert_eq!(renew().await, "superseded");
+    }
+
+    #[tokio::test]
+    async fn deletes_expired_certificates_after_retention() {
+        let (cert_manager, store, dir) = test_manager().await;
+        let watcher = watcher(&cert_manager, store.clone());
+        let old = cert_manager
+            .issue_certificate(request("old.example.com"))
+            .await
+            .unwrap();
+        let recent = cert_manager
+            .issue_certificate(request("new.example.com"))
+            .await
+            .unwrap();
+        let now = chrono::Utc::now().timestamp();
+        set_expiry(store.as_ref(), &old.certificate_id, now - 100 * DAY).await;
+        set_expiry(store.as_ref(), &recent.certificate_id, now - 10 * DAY).await;
+
+        watcher.expire_lapsed_certificates().await.unwrap();
+        watcher.cleanup_expired_certificates(90).await.unwrap();
+
+        assert!(store
+            .get_certificate(&old.certificate_id)
+            .await
+            .unwrap()
+            .is_none());
+        let recent = store
+            .get_certificate(&recent.certificate_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(recent.status, "expired");
+
+        let file = |id: &str, extension: &str| {
+            dir.path()
+                .join(format!("storage/{}.{}", id, extension))
+                .exists()
+        };
+        assert!(!file(&old.certificate_id, "crt"));
+        assert!(!file(&old.certificate_id, "key"));
+        assert!(file(&recent.certificate_id, "crt"));
+        assert!(file(&recent.certificate_id, "key"));
+    }
+}